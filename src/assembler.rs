@@ -0,0 +1,372 @@
+use crate::emulator::Emulator;
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+/// A problem with the source of a program, `line` counts from 1
+#[derive(Debug, PartialEq)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for AsmError {}
+
+/// A line of source that produces bytes
+struct Statement<'a> {
+    line: usize,
+    mnemonic: String,
+    operands: Vec<&'a str>
+}
+
+/// Assembles a program written with the same mnemonics the disassembler
+/// prints into a rom to be loaded at the program start.
+///
+/// Each line holds an optional `label:`, then an instruction such as
+/// `DRW V0, V1, 5`, `DB`/`DW` data or a `NAME = value` constant. Comments
+/// start with `;`. Numbers are decimal, `0x` hex or `0b` binary.
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    let mut symbols: HashMap<String, u16> = HashMap::new();
+    let mut statements = Vec::new();
+    let mut address = Emulator::PROGRAM_START;
+
+    // Work out where every label is and what every constant is
+    for (number, line) in source.lines().enumerate() {
+        let number = number + 1;
+        let error = |message: String| AsmError { line: number, message };
+        let mut line = line.split(';').next().unwrap_or("").trim();
+
+        if let Some(colon) = line.find(':') {
+            let label = line[..colon].trim();
+            check_name(label).map_err(error)?;
+            if address >= Emulator::MEMORY_SIZE {
+                return Err(error(format!("{} is out of memory", label)));
+            }
+            if symbols.insert(label.to_uppercase(), address as u16).is_some() {
+                return Err(error(format!("{} is already defined", label)));
+            }
+            line = line[colon + 1..].trim();
+        }
+
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(equals) = line.find('=') {
+            let name = line[..equals].trim();
+            check_name(name).map_err(error)?;
+            let value = value(line[equals + 1..].trim(), &symbols).map_err(error)?;
+            if symbols.insert(name.to_uppercase(), value).is_some() {
+                return Err(error(format!("{} is already defined", name)));
+            }
+            continue;
+        }
+
+        let (mnemonic, operands) = match line.find(char::is_whitespace) {
+            Some(space) => (&line[..space], line[space..].split(',').map(str::trim).collect()),
+            None => (line, Vec::new())
+        };
+        let statement = Statement { line: number, mnemonic: mnemonic.to_uppercase(), operands };
+
+        address += size(&statement);
+        if address > Emulator::MEMORY_SIZE {
+            return Err(error("Program does not fit in memory".to_string()));
+        }
+
+        statements.push(statement);
+    }
+
+    let mut rom = Vec::new();
+
+    for statement in statements.iter() {
+        let bytes = encode(statement, &symbols)
+            .map_err(|message| AsmError { line: statement.line, message })?;
+        rom.extend(bytes);
+    }
+
+    Ok(rom)
+}
+
+fn check_name(name: &str) -> Result<(), String> {
+    let valid = name.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && register(name).is_none();
+
+    if valid { Ok(()) } else { Err(format!("{} is not a valid name", name)) }
+}
+
+/// How many bytes a statement assembles to
+fn size(statement: &Statement) -> usize {
+    match statement.mnemonic.as_str() {
+        "DB" => statement.operands.len(),
+        "DW" => statement.operands.len() * 2,
+        "LD" if is_long(&statement.operands) => 4,
+        _ => 2
+    }
+}
+
+/// Whether the operands are for the XO-CHIP `LD I, LONG NNNN`
+fn is_long(operands: &[&str]) -> bool {
+    operands.len() == 2
+        && operands[0].eq_ignore_ascii_case("I")
+        && operands[1].to_uppercase().starts_with("LONG ")
+}
+
+/// A number, or the value of a label or constant
+fn value(operand: &str, symbols: &HashMap<String, u16>) -> Result<u16, String> {
+    let lower = operand.to_lowercase();
+    let parsed = if let Some(hex) = lower.strip_prefix("0x") {
+        u16::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = lower.strip_prefix("0b") {
+        u16::from_str_radix(binary, 2).ok()
+    } else if lower.starts_with(|c: char| c.is_ascii_digit()) {
+        lower.parse().ok()
+    } else {
+        return symbols.get(&operand.to_uppercase()).copied()
+            .ok_or(format!("{} is not defined", operand));
+    };
+
+    parsed.ok_or(format!("{} is not a valid number", operand))
+}
+
+/// A value that has to fit in `bits` bits
+fn sized_value(operand: &str, bits: u32, symbols: &HashMap<String, u16>) -> Result<u16, String> {
+    let value = value(operand, symbols)?;
+
+    if bits < 16 && value >> bits != 0 {
+        return Err(format!("{} does not fit in {} bits", operand, bits));
+    }
+
+    Ok(value)
+}
+
+/// The register number for `V0` to `VF`
+fn register(operand: &str) -> Option<u16> {
+    let mut chars = operand.chars();
+
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some('V'), Some(digit), None) | (Some('v'), Some(digit), None) => {
+            digit.to_digit(16).map(|digit| digit as u16)
+        },
+        _ => None
+    }
+}
+
+/// The registers of a `Vx-Vy` range
+fn register_range(operand: &str) -> Option<(u16, u16)> {
+    let mut parts = operand.split('-').map(str::trim);
+
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(x), Some(y), None) => Some((register(x)?, register(y)?)),
+        _ => None
+    }
+}
+
+fn encode(statement: &Statement, symbols: &HashMap<String, u16>) -> Result<Vec<u8>, String> {
+    let operands = &statement.operands;
+    let upper: Vec<String> = operands.iter().map(|operand| operand.to_uppercase()).collect();
+    let upper: Vec<&str> = upper.iter().map(String::as_str).collect();
+    let v = |i: usize| operands.get(i).and_then(|operand| register(operand));
+    let number = |i: usize, bits: u32| sized_value(operands[i], bits, symbols);
+    let invalid = || format!("Invalid operands for {}: {}", statement.mnemonic, operands.join(", "));
+
+    let opcode: u16 = match (statement.mnemonic.as_str(), operands.len()) {
+        ("DB", _) => {
+            return operands.iter().map(|operand| {
+                sized_value(operand, 8, symbols).map(|byte| byte as u8)
+            }).collect();
+        },
+        ("DW", _) => {
+            let mut bytes = Vec::new();
+            for operand in operands.iter() {
+                bytes.extend(&sized_value(operand, 16, symbols)?.to_be_bytes());
+            }
+            return Ok(bytes);
+        },
+        ("CLS", 0) => 0x00E0,
+        ("RET", 0) => 0x00EE,
+        ("SCR", 0) => 0x00FB,
+        ("SCL", 0) => 0x00FC,
+        ("EXIT", 0) => 0x00FD,
+        ("LOW", 0) => 0x00FE,
+        ("HIGH", 0) => 0x00FF,
+        ("SCD", 1) => 0x00C0 | number(0, 4)?,
+        ("SCU", 1) => 0x00D0 | number(0, 4)?,
+        ("JP", 1) => 0x1000 | number(0, 12)?,
+        ("JP", 2) if upper[0] == "V0" => 0xB000 | number(1, 12)?,
+        ("CALL", 1) => 0x2000 | number(0, 12)?,
+        ("SE", 2) | ("SNE", 2) => {
+            let x = v(0).ok_or_else(invalid)?;
+            let not = statement.mnemonic == "SNE";
+
+            match v(1) {
+                Some(y) => (if not { 0x9000 } else { 0x5000 }) | x << 8 | y << 4,
+                None => (if not { 0x4000 } else { 0x3000 }) | x << 8 | number(1, 8)?
+            }
+        },
+        ("LD", 2) => {
+            if is_long(operands) {
+                let address = sized_value(operands[1][5..].trim(), 16, symbols)?;
+                let mut bytes = vec![0xF0, 0x00];
+                bytes.extend(&address.to_be_bytes());
+                return Ok(bytes);
+            }
+
+            match (upper[0], upper[1], v(0), v(1)) {
+                (_, _, Some(x), Some(y)) => 0x8000 | x << 8 | y << 4,
+                (_, "DT", Some(x), None) => 0xF007 | x << 8,
+                (_, "K", Some(x), None) => 0xF00A | x << 8,
+                (_, "[I]", Some(x), None) => 0xF065 | x << 8,
+                (_, "R", Some(x), None) => 0xF085 | x << 8,
+                (_, _, Some(x), None) => 0x6000 | x << 8 | number(1, 8)?,
+                ("I", _, None, None) => 0xA000 | number(1, 12)?,
+                ("DT", _, None, Some(x)) => 0xF015 | x << 8,
+                ("ST", _, None, Some(x)) => 0xF018 | x << 8,
+                ("F", _, None, Some(x)) => 0xF029 | x << 8,
+                ("HF", _, None, Some(x)) => 0xF030 | x << 8,
+                ("B", _, None, Some(x)) => 0xF033 | x << 8,
+                ("[I]", _, None, Some(x)) => 0xF055 | x << 8,
+                ("R", _, None, Some(x)) => 0xF075 | x << 8,
+                ("[I]", _, None, None) => match register_range(operands[1]) {
+                    Some((x, y)) => 0x5002 | x << 8 | y << 4,
+                    None => return Err(invalid())
+                },
+                (_, "[I]", None, None) => match register_range(operands[0]) {
+                    Some((x, y)) => 0x5003 | x << 8 | y << 4,
+                    None => return Err(invalid())
+                },
+                _ => return Err(invalid())
+            }
+        },
+        ("ADD", 2) => match (upper[0], v(0), v(1)) {
+            ("I", None, Some(x)) => 0xF01E | x << 8,
+            (_, Some(x), Some(y)) => 0x8004 | x << 8 | y << 4,
+            (_, Some(x), None) => 0x7000 | x << 8 | number(1, 8)?,
+            _ => return Err(invalid())
+        },
+        ("OR", 2) | ("AND", 2) | ("XOR", 2) | ("SUB", 2) | ("SUBN", 2) | ("SHR", _) | ("SHL", _) => {
+            let x = v(0).ok_or_else(invalid)?;
+            // A single register shifts itself
+            let y = match operands.len() {
+                1 => x,
+                2 => v(1).ok_or_else(invalid)?,
+                _ => return Err(invalid())
+            };
+            let operation = match statement.mnemonic.as_str() {
+                "OR" => 0x1,
+                "AND" => 0x2,
+                "XOR" => 0x3,
+                "SUB" => 0x5,
+                "SHR" => 0x6,
+                "SUBN" => 0x7,
+                _ => 0xE
+            };
+
+            0x8000 | x << 8 | y << 4 | operation
+        },
+        ("RND", 2) => 0xC000 | v(0).ok_or_else(invalid)? << 8 | number(1, 8)?,
+        ("DRW", 3) => {
+            let x = v(0).ok_or_else(invalid)?;
+            let y = v(1).ok_or_else(invalid)?;
+
+            0xD000 | x << 8 | y << 4 | number(2, 4)?
+        },
+        ("SKP", 1) => 0xE09E | v(0).ok_or_else(invalid)? << 8,
+        ("SKNP", 1) => 0xE0A1 | v(0).ok_or_else(invalid)? << 8,
+        ("PLANE", 1) => 0xF001 | number(0, 2)? << 8,
+        ("AUDIO", 0) => 0xF002,
+        ("PITCH", 1) => 0xF03A | v(0).ok_or_else(invalid)? << 8,
+        (mnemonic, _) if is_mnemonic(mnemonic) => return Err(invalid()),
+        (mnemonic, _) => return Err(format!("Unknown instruction {}", mnemonic))
+    };
+
+    Ok(opcode.to_be_bytes().to_vec())
+}
+
+fn is_mnemonic(mnemonic: &str) -> bool {
+    [
+        "CLS", "RET", "SCR", "SCL", "EXIT", "LOW", "HIGH", "SCD", "SCU", "JP", "CALL", "SE",
+        "SNE", "LD", "ADD", "OR", "AND", "XOR", "SUB", "SUBN", "SHR", "SHL", "RND", "DRW", "SKP",
+        "SKNP", "PLANE", "AUDIO", "PITCH"
+    ].contains(&mnemonic)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disassembler::disassemble;
+
+    #[test]
+    fn labels_and_constants() {
+        let source = "
+            SPEED = 2          ; How far to move each frame
+            start:
+                LD I, sprite
+                ADD V0, SPEED
+                DRW V0, V1, 2
+                JP start
+            sprite: DB 0b11110000, 0x90
+        ";
+
+        assert_eq!(
+            Ok(vec![0xA2, 0x08, 0x70, 0x02, 0xD0, 0x12, 0x12, 0x00, 0xF0, 0x90]),
+            assemble(source)
+        );
+    }
+
+    #[test]
+    fn extended_instructions() {
+        let source = "HIGH\nLD I, LONG 0x1234\nLD [I], V2-V5\nPLANE 3\nDW 0xBEEF";
+
+        assert_eq!(
+            Ok(vec![0x00, 0xFF, 0xF0, 0x00, 0x12, 0x34, 0x52, 0x52, 0xF3, 0x01, 0xBE, 0xEF]),
+            assemble(source)
+        );
+    }
+
+    #[test]
+    fn errors_have_line_numbers() {
+        assert_eq!(
+            Err(AsmError { line: 2, message: "missing is not defined".to_string() }),
+            assemble("CLS\nJP missing")
+        );
+        assert_eq!(
+            Err(AsmError { line: 1, message: "0x100 does not fit in 8 bits".to_string() }),
+            assemble("LD V0, 0x100")
+        );
+        assert_eq!(
+            Err(AsmError { line: 1, message: "Unknown instruction MOV".to_string() }),
+            assemble("MOV V0, V1")
+        );
+
+        // A label after a program that fills memory has no address
+        let line = format!("DB {}\n", vec!["0"; 256].join(", "));
+        let full = line.repeat((Emulator::MEMORY_SIZE - Emulator::PROGRAM_START) / 256);
+        assert!(assemble(&full).is_ok());
+        assert_eq!(
+            Err(AsmError { line: 255, message: "end is out of memory".to_string() }),
+            assemble(&(full + "end:"))
+        );
+    }
+
+    #[test]
+    fn disassembly_round_trips() {
+        let rom = include_bytes!("../data/pong.ch8");
+        let source: Vec<String> = disassemble(rom, Emulator::PROGRAM_START as u16)
+            .into_iter()
+            .map(|line| match line.label {
+                Some(label) => format!("{}: {}", label, line.text),
+                None => line.text
+            })
+            .collect();
+
+        assert_eq!(Ok(rom.to_vec()), assemble(&source.join("\n")));
+    }
+}
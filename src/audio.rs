@@ -0,0 +1,244 @@
+//! Sound for the frontends, free of any audio library so the samples can be
+//! tested and written to files as well as played.
+
+use crate::emulator::Emulator;
+
+use std::f32::consts::PI;
+use std::io::{self, Seek, SeekFrom, Write};
+
+/// The shape of the tone played while the sound timer is running
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Waveform {
+    Square,
+    Triangle,
+    Sawtooth,
+    Sine
+}
+
+impl Waveform {
+    pub const NAMES: [&'static str; 4] = ["square", "triangle", "sawtooth", "sine"];
+
+    pub fn from_name(name: &str) -> Option<Waveform> {
+        match name {
+            "square" => Some(Waveform::Square),
+            "triangle" => Some(Waveform::Triangle),
+            "sawtooth" => Some(Waveform::Sawtooth),
+            "sine" => Some(Waveform::Sine),
+            _ => None
+        }
+    }
+
+    /// The value of the wave from -1 to 1, `phase` going from 0 to 1 over
+    /// one period
+    fn sample(&self, phase: f32) -> f32 {
+        match self {
+            Waveform::Square => if phase < 0.5 { 1.0 } else { -1.0 },
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+            Waveform::Sawtooth => 2.0 * phase - 1.0,
+            Waveform::Sine => (2.0 * PI * phase).sin()
+        }
+    }
+}
+
+/// What the beeper sounds like
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Tone {
+    /// In Hz
+    pub frequency: f32,
+    /// From 0 for silence to 1 for full scale
+    pub volume: f32,
+    pub waveform: Waveform
+}
+
+impl Default for Tone {
+    fn default() -> Tone {
+        Tone { frequency: 440.0, volume: 0.25, waveform: Waveform::Square }
+    }
+}
+
+/// Generates the samples of a tone that is switched on and off as the sound
+/// timer runs, or of the XO-CHIP audio pattern when a program has loaded one.
+/// The phase carries over from one buffer to the next so the tone doesn't
+/// click.
+pub struct Beeper {
+    tone: Tone,
+    sample_rate: u32,
+    phase: f32,
+    active: bool,
+    pattern: Option<[u8; 16]>,
+    /// In bits per second
+    pattern_rate: f32,
+    /// Which of the 128 bits of the pattern is playing
+    pattern_position: f32
+}
+
+impl Beeper {
+    pub fn new(tone: Tone, sample_rate: u32) -> Beeper {
+        Beeper {
+            tone,
+            sample_rate,
+            phase: 0.0,
+            active: false,
+            pattern: None,
+            pattern_rate: 0.0,
+            pattern_position: 0.0
+        }
+    }
+
+    /// Turns the sound on or off, usually with `Emulator::is_sound_active`
+    pub fn set_active(&mut self, active: bool) {
+        self.active = active;
+    }
+
+    /// Plays an XO-CHIP audio pattern at `rate` bits per second in place of
+    /// the tone, or the tone again if there's no pattern
+    pub fn set_pattern(&mut self, pattern: Option<&[u8; 16]>, rate: f32) {
+        self.pattern = pattern.copied();
+        self.pattern_rate = rate;
+    }
+
+    /// Follows the sound timer and audio pattern of the emulator
+    pub fn update(&mut self, emu: &Emulator) {
+        self.set_active(emu.is_sound_active());
+        self.set_pattern(emu.audio_pattern(), emu.audio_playback_rate());
+    }
+
+    /// Fills `out` with mono samples, silence while the beeper is off
+    pub fn fill(&mut self, out: &mut [f32]) {
+        let step = self.tone.frequency / self.sample_rate as f32;
+        let pattern_step = self.pattern_rate / self.sample_rate as f32;
+
+        for sample in out.iter_mut() {
+            *sample = match (self.active, self.pattern) {
+                (false, _) => 0.0,
+                (true, None) => self.tone.waveform.sample(self.phase) * self.tone.volume,
+                (true, Some(pattern)) => {
+                    let bit = self.pattern_position as usize;
+                    if pattern[bit / 8] & (0x80 >> (bit % 8)) != 0 {
+                        self.tone.volume
+                    } else {
+                        -self.tone.volume
+                    }
+                }
+            };
+
+            self.phase = (self.phase + step).fract();
+            self.pattern_position = (self.pattern_position + pattern_step) % 128.0;
+        }
+    }
+}
+
+/// Streams mono samples to a 16 bit PCM WAV file. The sizes in the header
+/// are filled in by `finish`.
+pub struct WavWriter<W: Write + Seek> {
+    writer: W,
+    samples: u32
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(mut writer: W, sample_rate: u32) -> io::Result<WavWriter<W>> {
+        writer.write_all(b"RIFF")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        writer.write_all(b"WAVEfmt ")?;
+        writer.write_all(&16u32.to_le_bytes())?;
+        // PCM with one channel
+        writer.write_all(&1u16.to_le_bytes())?;
+        writer.write_all(&1u16.to_le_bytes())?;
+        writer.write_all(&sample_rate.to_le_bytes())?;
+        // Bytes per second, bytes per sample and bits per sample
+        writer.write_all(&(sample_rate * 2).to_le_bytes())?;
+        writer.write_all(&2u16.to_le_bytes())?;
+        writer.write_all(&16u16.to_le_bytes())?;
+        writer.write_all(b"data")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+
+        Ok(WavWriter { writer, samples: 0 })
+    }
+
+    /// Writes samples from -1 to 1, clipping anything louder
+    pub fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+        let bytes: Vec<u8> = samples.iter()
+            .flat_map(|sample| ((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16).to_le_bytes())
+            .collect();
+
+        self.writer.write_all(&bytes)?;
+        self.samples += samples.len() as u32;
+        Ok(())
+    }
+
+    /// Fills in the sizes in the header, returning the writer
+    pub fn finish(mut self) -> io::Result<W> {
+        let data_size = self.samples * 2;
+
+        self.writer.seek(SeekFrom::Start(4))?;
+        self.writer.write_all(&(36 + data_size).to_le_bytes())?;
+        self.writer.seek(SeekFrom::Start(40))?;
+        self.writer.write_all(&data_size.to_le_bytes())?;
+        self.writer.flush()?;
+
+        Ok(self.writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn waveforms() {
+        assert_eq!(1.0, Waveform::Square.sample(0.25));
+        assert_eq!(-1.0, Waveform::Square.sample(0.75));
+        assert_eq!(1.0, Waveform::Triangle.sample(0.5));
+        assert_eq!(-1.0, Waveform::Sawtooth.sample(0.0));
+        assert!(Waveform::Sine.sample(0.5).abs() < 1e-6);
+        assert_eq!(Some(Waveform::Sine), Waveform::from_name("sine"));
+    }
+
+    #[test]
+    fn beeper_is_silent_until_active() {
+        // Two samples per period at 1/2 volume
+        let mut beeper = Beeper::new(Tone { frequency: 2.0, volume: 0.5, waveform: Waveform::Square }, 4);
+        let mut out = [1.0; 4];
+
+        beeper.fill(&mut out);
+        assert_eq!([0.0; 4], out);
+
+        beeper.set_active(true);
+        beeper.fill(&mut out);
+        assert_eq!([0.5, -0.5, 0.5, -0.5], out);
+
+        // The phase carries on from the last buffer
+        let mut odd = [0.0; 3];
+        beeper.fill(&mut odd);
+        beeper.fill(&mut out);
+        assert_eq!([-0.5, 0.5, -0.5, 0.5], out);
+    }
+
+    #[test]
+    fn audio_patterns() {
+        // One bit per sample, alternating on and off a byte at a time
+        let mut beeper = Beeper::new(Tone { volume: 1.0, ..Tone::default() }, 8);
+        let mut pattern = [0; 16];
+        pattern[0] = 0xF0;
+        beeper.set_pattern(Some(&pattern), 8.0);
+        beeper.set_active(true);
+
+        let mut out = [0.0; 8];
+        beeper.fill(&mut out);
+        assert_eq!([1.0, 1.0, 1.0, 1.0, -1.0, -1.0, -1.0, -1.0], out);
+    }
+
+    #[test]
+    fn wav_header() {
+        let mut wav = WavWriter::new(io::Cursor::new(Vec::new()), 44_100).unwrap();
+        wav.write(&[0.0, 1.0, -2.0]).unwrap();
+        let bytes = wav.finish().unwrap().into_inner();
+
+        assert_eq!(44 + 6, bytes.len());
+        assert_eq!(b"RIFF", &bytes[0..4]);
+        assert_eq!(42u32.to_le_bytes(), bytes[4..8]);
+        assert_eq!(44_100u32.to_le_bytes(), bytes[24..28]);
+        assert_eq!(6u32.to_le_bytes(), bytes[40..44]);
+        assert_eq!([0, 0, 0xFF, 0x7F, 0x01, 0x80], bytes[44..]);
+    }
+}
@@ -0,0 +1,345 @@
+//! Runs a rom without a display for a fixed number of cycles or frames and
+//! dumps the final state of the emulator to stdout, for use in CI. The random
+//! numbers are seeded with 0 unless `--seed` is given, so runs are repeatable.
+
+use chip8::{Emulator, KeyState, Palette, Pixel, Quirks};
+use chip8::audio::{Beeper, Tone, WavWriter};
+use chip8::image::Image;
+use chip8::recording::Recorder;
+
+use std::env;
+use std::fs::{self, File};
+use std::io::BufWriter;
+
+const USAGE: &str = "Usage: chip8-headless [--quirks NAME] [--cycles N | --frames N] \
+[--ipf N] [--input FILE] [--seed N] [--format ascii|pbm] [--audio-out FILE] \
+[--screenshot FILE] [--record FILE.gif|DIR] [--scale N] [--palette NAME] ROM";
+// Exactly 735 samples of audio are rendered for every frame
+const SAMPLE_RATE: u32 = 44_100;
+const SAMPLES_PER_FRAME: usize = SAMPLE_RATE as usize / 60;
+
+/// How the final framebuffer is written
+enum Format {
+    Ascii,
+    Pbm
+}
+
+/// A key changing state at the start of a frame
+struct Input {
+    frame: u64,
+    key: u8,
+    state: KeyState
+}
+
+/// Options given on the command line
+struct Options {
+    rom: String,
+    quirks: Quirks,
+    cycles: u64,
+    cycles_per_frame: u64,
+    inputs: Vec<Input>,
+    seed: u64,
+    format: Format,
+    /// Where to write the sound as a WAV file
+    audio_out: Option<String>,
+    /// Where to write a PNG of the final framebuffer
+    screenshot: Option<String>,
+    /// Where to record a GIF or PNG frames of every frame
+    record: Option<String>,
+    scale: u32,
+    palette: Palette
+}
+
+/// Parses an input timeline, one `<frame> <key> <down|up>` per line with the
+/// key in hex. Blank lines and lines starting with `#` are ignored.
+fn parse_inputs(source: &str) -> Result<Vec<Input>, String> {
+    let mut inputs = Vec::new();
+
+    for (number, line) in source.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let error = || format!("Invalid input on line {}: {}", number + 1, line);
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        if parts.len() != 3 {
+            return Err(error());
+        }
+
+        let frame = parts[0].parse().map_err(|_| error())?;
+        let key = u8::from_str_radix(parts[1], 16).map_err(|_| error())?;
+        let state = match parts[2] {
+            "down" => KeyState::DOWN,
+            "up" => KeyState::UP,
+            _ => return Err(error())
+        };
+
+        if key > 0xF {
+            return Err(error());
+        }
+
+        inputs.push(Input { frame, key, state });
+    }
+
+    inputs.sort_by_key(|input| input.frame);
+    Ok(inputs)
+}
+
+fn parse_number(name: &str, value: Option<&String>) -> Result<u64, String> {
+    value
+        .and_then(|value| value.parse().ok())
+        .ok_or(format!("{} needs a number", name))
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut rom = None;
+    let mut quirks = Quirks::default();
+    let mut cycles = None;
+    let mut frames = None;
+    let mut cycles_per_frame = 10;
+    let mut inputs = Vec::new();
+    let mut seed = 0;
+    let mut format = Format::Ascii;
+    let mut audio_out = None;
+    let mut screenshot = None;
+    let mut record = None;
+    let mut scale = 10;
+    let mut palette = Palette::default();
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--quirks" => {
+                let name = args.next().ok_or("--quirks needs a preset name")?;
+                quirks = Quirks::preset(name).ok_or(format!(
+                    "Unknown quirks preset {}, expected one of {}",
+                    name,
+                    Quirks::PRESET_NAMES.join(", ")
+                ))?;
+            },
+            "--cycles" => cycles = Some(parse_number(arg, args.next())?),
+            "--frames" => frames = Some(parse_number(arg, args.next())?),
+            "--ipf" => {
+                cycles_per_frame = Some(parse_number(arg, args.next())?)
+                    .filter(|ipf| *ipf > 0)
+                    .ok_or("--ipf needs a number of instructions per frame")?;
+            },
+            "--input" => {
+                let path = args.next().ok_or("--input needs a path")?;
+                let source = fs::read_to_string(path)
+                    .map_err(|e| format!("Could not read {}: {}", path, e))?;
+                inputs = parse_inputs(&source)?;
+            },
+            "--seed" => seed = parse_number(arg, args.next())?,
+            "--format" => format = match args.next().map(|f| f.as_str()) {
+                Some("ascii") => Format::Ascii,
+                Some("pbm") => Format::Pbm,
+                _ => return Err("--format needs to be ascii or pbm".to_string())
+            },
+            "--audio-out" => audio_out = Some(args.next().ok_or("--audio-out needs a path")?.clone()),
+            "--screenshot" => screenshot = Some(args.next().ok_or("--screenshot needs a path")?.clone()),
+            "--record" => record = Some(args.next().ok_or("--record needs a path")?.clone()),
+            "--scale" => scale = parse_number(arg, args.next())?.clamp(1, 100) as u32,
+            "--palette" => palette = Palette::parse(args.next().ok_or("--palette needs a name or colours")?)?,
+            _ if arg.starts_with("--") => return Err(format!("Unknown option {}", arg)),
+            _ if rom.is_some() => return Err(format!("Only one rom can be given, not {} as well", arg)),
+            _ => rom = Some(arg.clone())
+        }
+    }
+
+    let cycles = match (cycles, frames) {
+        (Some(_), Some(_)) => return Err("Only one of --cycles and --frames can be given".to_string()),
+        (Some(cycles), None) => cycles,
+        (None, Some(frames)) => frames.checked_mul(cycles_per_frame)
+            .ok_or("--frames times --ipf is too many instructions to run")?,
+        (None, None) => return Err(USAGE.to_string())
+    };
+
+    Ok(Options {
+        rom: rom.ok_or(USAGE)?,
+        quirks,
+        cycles,
+        cycles_per_frame,
+        inputs,
+        seed,
+        format,
+        audio_out,
+        screenshot,
+        record,
+        scale,
+        palette
+    })
+}
+
+/// The framebuffer with `#` for lit pixels
+fn ascii_framebuffer(emu: &Emulator) -> String {
+    let mut out = String::new();
+
+    for y in 0..emu.screen_height() {
+        for x in 0..emu.screen_width() {
+            out.push(match emu.get_pixel(x, y) {
+                Pixel::ON => '#',
+                Pixel::OFF => '.'
+            });
+        }
+        out.push('\n');
+    }
+
+    out
+}
+
+/// The framebuffer as a plain (P1) portable bitmap
+fn pbm_framebuffer(emu: &Emulator) -> String {
+    let mut out = format!("P1\n{} {}\n", emu.screen_width(), emu.screen_height());
+
+    for y in 0..emu.screen_height() {
+        let line: Vec<&str> = (0..emu.screen_width())
+            .map(|x| match emu.get_pixel(x, y) {
+                Pixel::ON => "1",
+                Pixel::OFF => "0"
+            })
+            .collect();
+        out.push_str(&line.join(" "));
+        out.push('\n');
+    }
+
+    out
+}
+
+/// 64 bit FNV-1a, enough to tell whether two runs left memory the same
+fn memory_hash(memory: &[u8]) -> u64 {
+    memory.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+fn main() -> Result<(), String> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let options = parse_args(&args)?;
+
+    let mut emu = Emulator::load(&options.rom, options.quirks).map_err(|e| e.to_string())?;
+    emu.seed_rng(options.seed);
+    let mut inputs = options.inputs.into_iter().peekable();
+    let mut audio = match &options.audio_out {
+        Some(path) => {
+            let file = File::create(path).map_err(|e| format!("Could not create {}: {}", path, e))?;
+            let wav = WavWriter::new(BufWriter::new(file), SAMPLE_RATE).map_err(|e| e.to_string())?;
+            Some((Beeper::new(Tone::default(), SAMPLE_RATE), wav))
+        },
+        None => None
+    };
+    let mut recorder = match &options.record {
+        Some(path) => {
+            let width = Emulator::HIRES_SCREEN_WIDTH as u32 * options.scale;
+            let height = Emulator::HIRES_SCREEN_HEIGHT as u32 * options.scale;
+            Some(Recorder::create(path, width, height).map_err(|e| format!("Could not record to {}: {}", path, e))?)
+        },
+        None => None
+    };
+    let mut samples = [0.0; SAMPLES_PER_FRAME];
+    let mut cycles = options.cycles;
+    let mut frame = 0;
+
+    // The last frame runs short when the cycles don't divide into frames
+    while cycles > 0 && !emu.has_exited() {
+        // Apply the inputs at the start of each frame
+        while let Some(input) = inputs.next_if(|input| input.frame <= frame) {
+            emu.set_key(input.key, input.state);
+        }
+
+        let cycles_this_frame = cycles.min(options.cycles_per_frame);
+        emu.run_frame(cycles_this_frame as usize).map_err(|e| e.to_string())?;
+
+        if let Some((beeper, wav)) = &mut audio {
+            beeper.update(&emu);
+            beeper.fill(&mut samples);
+            wav.write(&samples).map_err(|e| e.to_string())?;
+        }
+
+        if let Some(recorder) = &mut recorder {
+            recorder.write(&Image::capture(&emu, &options.palette, options.scale)).map_err(|e| e.to_string())?;
+        }
+
+        cycles -= cycles_this_frame;
+        frame += 1;
+    }
+
+    if let Some((_, wav)) = audio {
+        wav.finish().map_err(|e| e.to_string())?;
+    }
+
+    if let Some(recorder) = recorder {
+        recorder.finish().map_err(|e| e.to_string())?;
+    }
+
+    if let Some(path) = &options.screenshot {
+        let file = File::create(path).map_err(|e| format!("Could not create {}: {}", path, e))?;
+        Image::capture(&emu, &options.palette, options.scale)
+            .write_png(BufWriter::new(file))
+            .map_err(|e| format!("Could not write {}: {}", path, e))?;
+    }
+
+    match options.format {
+        Format::Ascii => print!("{}", ascii_framebuffer(&emu)),
+        Format::Pbm => print!("{}", pbm_framebuffer(&emu))
+    }
+
+    let registers: Vec<String> = emu.registers().iter()
+        .enumerate()
+        .map(|(i, value)| format!("V{:X}={:02X}", i, value))
+        .collect();
+    println!("{}", registers.join(" "));
+    println!(
+        "I={:04X} PC={:04X} SP={} DT={:02X} ST={:02X}",
+        emu.index_register(),
+        emu.program_counter,
+        emu.stack().len(),
+        emu.delay_timer(),
+        emu.sound_timer()
+    );
+    println!("MEMORY={:016x}", memory_hash(&emu.memory));
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inputs_are_sorted_by_frame() {
+        let inputs = parse_inputs("# Press 5 and then A\n20 5 up\n\n10 5 down\n30 a down\n").unwrap();
+
+        let frames: Vec<u64> = inputs.iter().map(|input| input.frame).collect();
+        assert_eq!(vec![10, 20, 30], frames);
+        assert_eq!(0xA, inputs[2].key);
+    }
+
+    #[test]
+    fn invalid_inputs() {
+        assert!(parse_inputs("10 5").is_err());
+        assert!(parse_inputs("10 10 down").is_err());
+        assert!(parse_inputs("10 5 pressed").is_err());
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn too_many_frames() {
+        assert!(parse_args(&args(&["--frames", "18446744073709551615", "--ipf", "2", "rom.ch8"])).is_err());
+    }
+
+    #[test]
+    fn invalid_args() {
+        assert!(parse_args(&args(&["--frames", "10", "--ipf", "5", "rom.ch8"])).is_ok());
+        assert!(parse_args(&args(&["--frames", "10", "--ipf", "0", "rom.ch8"])).is_err());
+        assert_eq!(
+            Some("Unknown option --frame".to_string()),
+            parse_args(&args(&["--frame", "10", "rom.ch8"])).err()
+        );
+        assert!(parse_args(&args(&["--frames", "10", "rom.ch8", "other.ch8"])).is_err());
+    }
+}
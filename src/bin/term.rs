@@ -0,0 +1,228 @@
+//! Plays a rom in the terminal, for machines without a display. The display
+//! is drawn with half block characters in 24 bit colour, and keys are read as
+//! they're typed and bound the same way as in the windowed emulator. Escape or
+//! Ctrl-C quits.
+
+use chip8::{Config, Emulator, KeyState, Palette, Quirks};
+use chip8::filter::{DisplayFilter, Filter};
+use chip8::terminal::{self, HeldKeys};
+
+use std::env;
+use std::io::{self, Read, Write};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const USAGE: &str = "Usage: chip8-term [--quirks NAME] [--ipf N] [--seed N] [--palette NAME] \
+[--filter NAME] [--config FILE] ROM";
+const FRAME_TIME: Duration = Duration::from_nanos(1_000_000_000 / 60);
+
+/// Options given on the command line
+struct Options {
+    rom: String,
+    quirks: Quirks,
+    cycles_per_frame: usize,
+    seed: Option<u64>,
+    palette: Option<Palette>,
+    filter: Option<Filter>,
+    /// Settings to use in place of the usual config file
+    config: Option<String>
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut rom = None;
+    let mut quirks = Quirks::default();
+    let mut cycles_per_frame = 10;
+    let mut seed = None;
+    let mut palette = None;
+    let mut filter = None;
+    let mut config = None;
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--quirks" => {
+                let name = args.next().ok_or("--quirks needs a preset name")?;
+                quirks = Quirks::preset(name).ok_or(format!(
+                    "Unknown quirks preset {}, expected one of {}",
+                    name,
+                    Quirks::PRESET_NAMES.join(", ")
+                ))?;
+            },
+            "--ipf" => {
+                cycles_per_frame = args.next()
+                    .and_then(|ipf| ipf.parse().ok())
+                    .filter(|ipf| *ipf > 0)
+                    .ok_or("--ipf needs a number of instructions per frame")?;
+            },
+            "--seed" => {
+                seed = Some(args.next()
+                    .and_then(|seed| seed.parse().ok())
+                    .ok_or("--seed needs a number")?);
+            },
+            "--palette" => {
+                palette = Some(Palette::parse(args.next().ok_or("--palette needs a name or colours")?)?);
+            },
+            "--filter" => {
+                let name = args.next().ok_or("--filter needs a name")?;
+                filter = Some(Filter::from_name(name).ok_or(format!(
+                    "Unknown filter {}, expected one of {}",
+                    name,
+                    Filter::NAMES.join(", ")
+                ))?);
+            },
+            "--config" => config = Some(args.next().ok_or("--config needs a path")?.clone()),
+            _ if arg.starts_with("--") => return Err(format!("Unknown option {}", arg)),
+            _ if rom.is_some() => return Err(format!("Only one rom can be given, not {} as well", arg)),
+            _ => rom = Some(arg.clone())
+        }
+    }
+
+    Ok(Options {
+        rom: rom.ok_or(USAGE)?,
+        quirks,
+        cycles_per_frame,
+        seed,
+        palette,
+        filter,
+        config
+    })
+}
+
+/// Has the terminal pass on keys as they're typed without echoing them or
+/// handling Ctrl-C, and hides the cursor. The terminal is put back how it
+/// was when this is dropped.
+struct RawMode {
+    #[cfg(unix)]
+    saved: termios::Termios
+}
+
+impl RawMode {
+    #[cfg(unix)]
+    fn enable() -> Result<RawMode, String> {
+        use std::os::unix::io::AsRawFd;
+        use termios::{tcsetattr, Termios, ECHO, ICANON, ISIG, TCSANOW, VMIN, VTIME};
+
+        let stdin = io::stdin().as_raw_fd();
+        let saved = Termios::from_fd(stdin).map_err(|_| "Standard input is not a terminal".to_string())?;
+
+        let mut raw = saved;
+        raw.c_lflag &= !(ICANON | ECHO | ISIG);
+        raw.c_cc[VMIN] = 1;
+        raw.c_cc[VTIME] = 0;
+        tcsetattr(stdin, TCSANOW, &raw).map_err(|e| format!("Could not set up the terminal: {}", e))?;
+
+        print!("\x1b[?25l\x1b[2J");
+        Ok(RawMode { saved })
+    }
+
+    #[cfg(not(unix))]
+    fn enable() -> Result<RawMode, String> {
+        Err("chip8-term only runs in Unix terminals".to_string())
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        {
+            use std::os::unix::io::AsRawFd;
+            let _ = termios::tcsetattr(io::stdin().as_raw_fd(), termios::TCSANOW, &self.saved);
+        }
+
+        println!("\x1b[0m\x1b[?25h");
+    }
+}
+
+fn run(options: &Options) -> Result<(), String> {
+    let config = Config::find(options.config.as_deref(), &options.rom)?;
+    let palette = options.palette.clone().unwrap_or(config.palette);
+    let mut filter = DisplayFilter::new(options.filter.unwrap_or(config.filter));
+
+    let mut emu = Emulator::load(&options.rom, options.quirks).map_err(|e| e.to_string())?;
+    if let Some(seed) = options.seed {
+        emu.seed_rng(seed);
+    }
+
+    let _raw_mode = RawMode::enable()?;
+
+    // Read stdin on its own thread so the emulator doesn't wait for keys
+    let (sender, typed) = mpsc::channel();
+    thread::spawn(move || {
+        let mut buffer = [0; 64];
+        let stdin = io::stdin();
+        let mut stdin = stdin.lock();
+
+        while let Ok(read) = stdin.read(&mut buffer) {
+            if read == 0 || sender.send(buffer[..read].to_vec()).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut held = HeldKeys::new();
+    let mut frame: u64 = 0;
+    let mut width = 0;
+    let mut next_frame = Instant::now();
+
+    'main: loop {
+        while let Ok(bytes) = typed.try_recv() {
+            for name in terminal::key_names(&bytes) {
+                if name == "Escape" || name == "Ctrl-C" {
+                    break 'main;
+                }
+
+                if let Some(key) = config.keys.key(&name) {
+                    if held.press(key, frame) {
+                        emu.set_key(key, KeyState::DOWN);
+                    }
+                }
+            }
+        }
+
+        for key in held.release(frame) {
+            emu.set_key(key, KeyState::UP);
+        }
+
+        emu.run_frame(options.cycles_per_frame).map_err(|e| e.to_string())?;
+
+        // The program asked to stop with the SUPER-CHIP exit instruction
+        if emu.has_exited() {
+            break;
+        }
+
+        // Clear away the bigger display when switching from hires
+        let resized = width != emu.screen_width();
+        if resized {
+            width = emu.screen_width();
+            print!("\x1b[2J");
+        }
+
+        if resized || emu.draw || emu.clear || filter.is_active() {
+            let colours = filter.update(&emu, &palette);
+            let mut stdout = io::stdout();
+            stdout.write_all(terminal::render(colours, width as usize).as_bytes())
+                .and_then(|_| stdout.flush())
+                .map_err(|e| e.to_string())?;
+        }
+
+        frame += 1;
+
+        // Wait for the next frame, starting again from now if running the
+        // last one took longer than a frame
+        next_frame += FRAME_TIME;
+        match next_frame.checked_duration_since(Instant::now()) {
+            Some(wait) => thread::sleep(wait),
+            None => next_frame = Instant::now()
+        }
+    }
+
+    Ok(())
+}
+
+fn main() -> Result<(), String> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let options = parse_args(&args)?;
+
+    run(&options)
+}
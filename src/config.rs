@@ -0,0 +1,255 @@
+//! Settings for the frontends, read from a TOML file.
+//!
+//! Each Chip8 key, in hex, is bound to the name of a physical key or a list
+//! of them, and the same for controller buttons. The palette is the name of
+//! a preset or a list of two or four colours, and the filter one of
+//! `Filter::NAMES`. Roms can override any of the settings in a table named
+//! after their file name:
+//!
+//! ```toml
+//! palette = "amber"
+//!
+//! [keys]
+//! 5 = ["W", "Up"]
+//! 8 = ["S", "Down"]
+//!
+//! [buttons]
+//! 6 = ["a", "rightshoulder"]
+//!
+//! [roms."pong.ch8"]
+//! filter = "blend"
+//!
+//! [roms."pong.ch8".keys]
+//! 1 = ["W", "Up"]
+//! 4 = ["S", "Down"]
+//! C = "Keypad 8"
+//! D = "Keypad 2"
+//!
+//! [roms."pong.ch8".buttons]
+//! 1 = "dpup"
+//! 4 = "dpdown"
+//! C = "y"
+//! D = "a"
+//!
+//! [roms."octojam.ch8"]
+//! palette = ["#000000", "#FFFFFF", "#FF0000", "#0000FF"]
+//! ```
+//!
+//! Keys that aren't mentioned keep their default bindings.
+
+use crate::filter::Filter;
+use crate::keymap::KeyMap;
+use crate::palette::Palette;
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml::value::{Table, Value};
+
+/// The settings for running one rom
+#[derive(Clone, PartialEq, Debug)]
+pub struct Config {
+    pub keys: KeyMap,
+    /// For game controllers
+    pub buttons: KeyMap,
+    pub palette: Palette,
+    /// Smooths out flickering sprites
+    pub filter: Filter
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            keys: KeyMap::default(),
+            buttons: KeyMap::controller(),
+            palette: Palette::default(),
+            filter: Filter::default()
+        }
+    }
+}
+
+impl Config {
+    /// Reads the settings for the rom with the given file name, if any
+    pub fn parse(source: &str, rom: Option<&str>) -> Result<Config, String> {
+        let value: Value = source.parse().map_err(|e: toml::de::Error| e.to_string())?;
+        let table = value.as_table().ok_or("Expected a table of settings")?;
+        let mut config = Config::default();
+
+        config.apply(table, true)?;
+
+        let roms = table.get("roms").map(Value::as_table);
+        if let Some(None) = roms {
+            return Err("Expected a table of roms".to_string());
+        }
+
+        let overrides = rom.and_then(|rom| Some((rom, roms??.get(rom)?)));
+        if let Some((rom, overrides)) = overrides {
+            let overrides = overrides.as_table().ok_or(format!("Expected a table of settings for {}", rom))?;
+            config.apply(overrides, false).map_err(|e| format!("{}: {}", rom, e))?;
+        }
+
+        Ok(config)
+    }
+
+    /// Reads the settings for `rom` from a file, matching the overrides on
+    /// the rom's file name
+    pub fn load<P: AsRef<Path>>(path: P, rom: &str) -> Result<Config, String> {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        let rom = Path::new(rom).file_name().and_then(|name| name.to_str());
+
+        Config::parse(&source, rom).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// The config file read when none is given, in the XDG config directory
+    pub fn default_path() -> Option<PathBuf> {
+        let dir = env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+
+        Some(dir.join("chip8").join("config.toml"))
+    }
+
+    /// Reads the settings for `rom` from `path`, or from the default path
+    /// when there is a file there, falling back to the defaults
+    pub fn find(path: Option<&str>, rom: &str) -> Result<Config, String> {
+        match path {
+            Some(path) => Config::load(path, rom),
+            None => match Config::default_path() {
+                Some(path) if path.exists() => Config::load(path, rom),
+                _ => Ok(Config::default())
+            }
+        }
+    }
+
+    /// Changes the settings given in `table`, which may hold the overrides
+    /// for roms when it's the top level
+    fn apply(&mut self, table: &Table, top_level: bool) -> Result<(), String> {
+        for (name, value) in table {
+            match name.as_str() {
+                "keys" => bind_keys(&mut self.keys, value)?,
+                "buttons" => bind_keys(&mut self.buttons, value)?,
+                "palette" => self.palette = parse_palette(value)?,
+                "filter" => {
+                    self.filter = value.as_str()
+                        .and_then(Filter::from_name)
+                        .ok_or(format!("Expected the filter to be one of {}", Filter::NAMES.join(", ")))?;
+                },
+                "roms" if top_level => (),
+                _ => return Err(format!("Unknown setting {}", name))
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Binds the keys in a table of Chip8 keys to physical key or button names
+fn bind_keys(map: &mut KeyMap, value: &Value) -> Result<(), String> {
+    let table = value.as_table().ok_or("Expected a table of keys")?;
+
+    for (key, names) in table {
+        let chip8_key = u8::from_str_radix(key, 16).ok()
+            .filter(|key| *key < 16)
+            .ok_or(format!("Expected a key from 0 to F, got {}", key))?;
+        let error = || format!("Expected key {} to be bound to a key name or a list of them", key);
+
+        let names: Vec<&str> = match names {
+            Value::String(name) => vec![name.as_str()],
+            Value::Array(names) => names.iter()
+                .map(|name| name.as_str().ok_or_else(error))
+                .collect::<Result<_, _>>()?,
+            _ => return Err(error())
+        };
+
+        map.bind(chip8_key, &names);
+    }
+
+    Ok(())
+}
+
+/// Reads a palette given by name or as a list of colours
+fn parse_palette(value: &Value) -> Result<Palette, String> {
+    let error = || "Expected the palette to be a name or a list of colours".to_string();
+
+    match value {
+        Value::String(name) => Palette::parse(name),
+        Value::Array(colours) => Palette::from_hex(&colours.iter()
+            .map(|colour| colour.as_str().ok_or_else(error))
+            .collect::<Result<Vec<_>, _>>()?),
+        _ => Err(error())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = r##"
+        palette = "green"
+
+        [keys]
+        5 = ["W", "Up"]
+        8 = "Down"
+
+        [buttons]
+        6 = ["a", "rightshoulder"]
+
+        [roms."pong.ch8".keys]
+        1 = ["W", "Up"]
+        C = "Keypad 8"
+
+        [roms."pong.ch8".buttons]
+        1 = "dpup"
+        4 = "dpdown"
+
+        [roms."pong.ch8"]
+        palette = ["#000000", "#FF8800"]
+        filter = "blend"
+    "##;
+
+    #[test]
+    fn global_keys() {
+        let config = Config::parse(SOURCE, Some("maze.ch8")).unwrap();
+
+        assert_eq!(vec!["W", "Up"], config.keys.names(0x5));
+        assert_eq!(vec!["Down"], config.keys.names(0x8));
+        // Untouched keys keep their defaults
+        assert_eq!(Some(0x0), config.keys.key("X"));
+        assert_eq!(vec!["a", "rightshoulder"], config.buttons.names(0x6));
+        assert_eq!(Some(0x5), config.buttons.key("dpup"));
+        assert_eq!("green", config.palette.name);
+        assert_eq!(Filter::Off, config.filter);
+        assert_eq!(Config::parse(SOURCE, None), Ok(config));
+    }
+
+    #[test]
+    fn rom_overrides() {
+        let config = Config::parse(SOURCE, Some("pong.ch8")).unwrap();
+
+        assert_eq!(Some(0x1), config.keys.key("Up"));
+        assert_eq!(Some(0xC), config.keys.key("Keypad 8"));
+        assert!(config.keys.names(0x5).is_empty());
+        assert_eq!(vec!["Down"], config.keys.names(0x8));
+        assert_eq!(Some(0x1), config.buttons.key("dpup"));
+        assert_eq!(Some(0x4), config.buttons.key("dpdown"));
+        assert_eq!(Some(0x6), config.buttons.key("a"));
+        assert_eq!("#FF8800", config.palette.colour(1).to_string());
+        assert_eq!(Filter::Blend, config.filter);
+    }
+
+    #[test]
+    fn invalid_configs() {
+        assert!(Config::parse("[keys]\nG = \"X\"", None).is_err());
+        assert!(Config::parse("[keys]\n10 = \"X\"", None).is_err());
+        assert!(Config::parse("[keys]\n1 = 2", None).is_err());
+        assert!(Config::parse("[keys]\n1 = [\"X\", 2]", None).is_err());
+        assert!(Config::parse("[colours]", None).is_err());
+        assert!(Config::parse("palette = \"purple\"", None).is_err());
+        assert!(Config::parse("palette = [\"#000000\", 1]", None).is_err());
+        assert!(Config::parse("filter = \"blur\"", None).is_err());
+        assert!(Config::parse("[roms.\"pong.ch8\".roms]", Some("pong.ch8")).is_err());
+        assert!(Config::parse("keys = [", None).is_err());
+    }
+}
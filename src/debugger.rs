@@ -0,0 +1,439 @@
+use crate::disassembler;
+use crate::emulator::{Chip8Error, Emulator};
+
+use std::fmt;
+
+const HELP: &str = "\
+step [N]           run N instructions, 1 by default
+continue           run until a breakpoint is hit
+break ADDRESS      break when the program counter reaches ADDRESS, in hex
+break op PATTERN   break on opcodes matching PATTERN, . matches any digit, eg D..5
+delete N           remove breakpoint N
+breakpoints        list the breakpoints
+registers          print the registers, I, stack and timers
+memory START [N]   hexdump N bytes of memory from hex START, 64 by default
+disasm [N]         disassemble N instructions around the program counter
+quit               stop the emulator";
+
+/// A condition that pauses execution before an instruction is run
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Breakpoint {
+    Address(usize),
+    /// Opcodes which equal `value` in the bits set in `mask`
+    Opcode { value: u16, mask: u16 }
+}
+
+impl Breakpoint {
+    /// Parses an opcode pattern such as `D..5`, where `.` matches any digit
+    pub fn opcode(pattern: &str) -> Result<Breakpoint, String> {
+        let invalid = || format!("Invalid opcode pattern {}", pattern);
+
+        if pattern.chars().count() != 4 {
+            return Err(invalid());
+        }
+
+        let mut value = 0;
+        let mut mask = 0;
+
+        for digit in pattern.chars() {
+            value <<= 4;
+            mask <<= 4;
+
+            if digit != '.' {
+                value |= digit.to_digit(16).ok_or_else(invalid)? as u16;
+                mask |= 0xF;
+            }
+        }
+
+        Ok(Breakpoint::Opcode { value, mask })
+    }
+
+    fn matches(&self, pc: usize, opcode: u16) -> bool {
+        match *self {
+            Breakpoint::Address(address) => address == pc,
+            Breakpoint::Opcode { value, mask } => opcode & mask == value
+        }
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Breakpoint::Address(address) => write!(f, "address {:03X}", address),
+            Breakpoint::Opcode { value, mask } => {
+                let pattern: String = (0..4).rev().map(|digit| {
+                    let shift = digit * 4;
+                    match (mask >> shift) & 0xF {
+                        0 => '.',
+                        _ => std::char::from_digit(((value >> shift) & 0xF) as u32, 16)
+                            .unwrap()
+                            .to_ascii_uppercase()
+                    }
+                }).collect();
+                write!(f, "opcode {}", pattern)
+            }
+        }
+    }
+}
+
+/// Something typed at the debugger prompt
+#[derive(Clone, PartialEq, Debug)]
+pub enum Command {
+    Step(usize),
+    Continue,
+    Break(Breakpoint),
+    Delete(usize),
+    Breakpoints,
+    Registers,
+    Memory { start: usize, length: usize },
+    Disassemble(usize),
+    Help,
+    Quit
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Command, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let number = |i: usize, default: usize| match words.get(i) {
+            Some(word) => parse_number(word),
+            None => Ok(default)
+        };
+
+        let command = match words.as_slice() {
+            ["step"] | ["s"] | ["step", _] | ["s", _] => Command::Step(number(1, 1)?),
+            ["continue"] | ["c"] => Command::Continue,
+            ["break", "op", pattern] | ["b", "op", pattern] => {
+                Command::Break(Breakpoint::opcode(pattern)?)
+            },
+            ["break", address] | ["b", address] => {
+                Command::Break(Breakpoint::Address(parse_address(address)?))
+            },
+            ["delete", _] | ["d", _] => Command::Delete(number(1, 0)?),
+            ["breakpoints"] => Command::Breakpoints,
+            ["registers"] | ["r"] => Command::Registers,
+            ["memory", _] | ["memory", _, _] | ["m", _] | ["m", _, _] => Command::Memory {
+                start: parse_address(words[1])?,
+                length: number(2, 64)?
+            },
+            ["disasm"] | ["disasm", _] => Command::Disassemble(number(1, 9)?),
+            ["help"] | ["h"] => Command::Help,
+            ["quit"] | ["q"] => Command::Quit,
+            _ => return Err(format!("Unknown command {}, try help", line.trim()))
+        };
+
+        Ok(command)
+    }
+}
+
+/// Hex numbers with a leading `0x`, anything else is decimal
+fn parse_number(word: &str) -> Result<usize, String> {
+    match word.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => word.parse()
+    }.map_err(|_| format!("{} is not a number", word))
+}
+
+/// Addresses are hex, as the debugger prints them, with or without a `0x`
+fn parse_address(word: &str) -> Result<usize, String> {
+    let hex = word.strip_prefix("0x").unwrap_or(word);
+    usize::from_str_radix(hex, 16).map_err(|_| format!("{} is not an address", word))
+}
+
+/// Why the debugger stopped running instructions
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Stop {
+    /// All the requested instructions were run
+    Done,
+    /// The breakpoint at this index was hit
+    Breakpoint(usize),
+    /// The program stopped itself with 00FD
+    Exited
+}
+
+/// Holds the breakpoints for stepping through a program, and counts the
+/// instructions run so the timers tick as they would running frame by frame
+pub struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+    cycles_per_frame: usize,
+    /// The number of instructions run so far
+    cycle: u64,
+    /// The cycle the timers were last ticked for, so retrying an instruction
+    /// that failed doesn't tick them again
+    ticked: Option<u64>
+}
+
+impl Debugger {
+    pub fn new(cycles_per_frame: usize) -> Debugger {
+        Debugger {
+            breakpoints: Vec::new(),
+            cycles_per_frame: cycles_per_frame.max(1),
+            cycle: 0,
+            ticked: None
+        }
+    }
+
+    /// The number of instructions run so far
+    pub fn cycle(&self) -> u64 {
+        self.cycle
+    }
+
+    /// Whether the last instruction run was the last of its frame
+    pub fn is_frame_finished(&self) -> bool {
+        self.cycle.is_multiple_of(self.cycles_per_frame as u64)
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) {
+        self.breakpoints.push(breakpoint);
+    }
+
+    pub fn remove_breakpoint(&mut self, index: usize) -> Option<Breakpoint> {
+        if index < self.breakpoints.len() {
+            Some(self.breakpoints.remove(index))
+        } else {
+            None
+        }
+    }
+
+    /// The breakpoint the instruction at the program counter would hit
+    pub fn hit(&self, emu: &Emulator) -> Option<usize> {
+        let opcode = emu.peek_opcode().ok()?;
+
+        self.breakpoints.iter().position(|breakpoint| breakpoint.matches(emu.program_counter, opcode))
+    }
+
+    /// Runs the instruction at the program counter, ticking the timers first
+    /// if it starts a frame like `Emulator::run_frame` does
+    pub fn run_instruction(&mut self, emu: &mut Emulator) -> Result<(), Chip8Error> {
+        if self.is_frame_finished() && self.ticked != Some(self.cycle) {
+            emu.tick_timers();
+            self.ticked = Some(self.cycle);
+        }
+
+        emu.step()?;
+        self.cycle += 1;
+        Ok(())
+    }
+
+    /// Runs up to `count` instructions, stopping before any instruction
+    /// after the first that hits a breakpoint
+    pub fn step(&mut self, emu: &mut Emulator, count: usize) -> Result<Stop, Chip8Error> {
+        for i in 0..count {
+            if i > 0 {
+                if let Some(index) = self.hit(emu) {
+                    return Ok(Stop::Breakpoint(index));
+                }
+            }
+
+            self.run_instruction(emu)?;
+
+            if emu.has_exited() {
+                return Ok(Stop::Exited);
+            }
+        }
+
+        Ok(Stop::Done)
+    }
+
+    /// Runs a command that doesn't need the frontend, returning what to print.
+    /// `Continue` and `Quit` are left to the caller and print nothing.
+    pub fn execute(&mut self, emu: &mut Emulator, command: &Command) -> Result<String, Chip8Error> {
+        let output = match *command {
+            Command::Step(count) => {
+                let stop = self.step(emu, count)?;
+                let mut output = disassemble_around(emu, 1);
+
+                match stop {
+                    Stop::Breakpoint(index) => {
+                        output = format!("Hit breakpoint {}, {}\n{}", index, self.breakpoints[index], output);
+                    },
+                    Stop::Exited => output = "The program has exited".to_string(),
+                    Stop::Done => ()
+                };
+
+                output
+            },
+            Command::Break(breakpoint) => {
+                self.add_breakpoint(breakpoint);
+                format!("Breakpoint {}, {}", self.breakpoints.len() - 1, breakpoint)
+            },
+            Command::Delete(index) => match self.remove_breakpoint(index) {
+                Some(breakpoint) => format!("Deleted breakpoint {}, {}", index, breakpoint),
+                None => format!("There is no breakpoint {}", index)
+            },
+            Command::Breakpoints => {
+                let lines: Vec<String> = self.breakpoints.iter()
+                    .enumerate()
+                    .map(|(index, breakpoint)| format!("{}: {}", index, breakpoint))
+                    .collect();
+                lines.join("\n")
+            },
+            Command::Registers => registers(emu),
+            Command::Memory { start, length } => hexdump(emu, start, length),
+            Command::Disassemble(count) => disassemble_around(emu, count),
+            Command::Help => HELP.to_string(),
+            Command::Continue | Command::Quit => String::new()
+        };
+
+        Ok(output)
+    }
+}
+
+/// The registers, index register, stack and timers
+pub fn registers(emu: &Emulator) -> String {
+    let registers: Vec<String> = emu.registers().iter()
+        .enumerate()
+        .map(|(i, value)| format!("V{:X}={:02X}", i, value))
+        .collect();
+    let stack: Vec<String> = emu.stack().iter().map(|address| format!("{:03X}", address)).collect();
+
+    format!(
+        "{}\n{}\nPC={:03X} I={:03X} DT={:02X} ST={:02X}\nStack: [{}]",
+        registers[..8].join(" "),
+        registers[8..].join(" "),
+        emu.program_counter,
+        emu.index_register(),
+        emu.delay_timer(),
+        emu.sound_timer(),
+        stack.join(", ")
+    )
+}
+
+/// Sixteen bytes of memory per line, starting at `start`
+pub fn hexdump(emu: &Emulator, start: usize, length: usize) -> String {
+    let end = start.saturating_add(length).min(emu.memory.len());
+    let start = start.min(end);
+    let lines: Vec<String> = emu.memory[start..end]
+        .chunks(16)
+        .enumerate()
+        .map(|(i, chunk)| {
+            let bytes: Vec<String> = chunk.iter().map(|byte| format!("{:02X}", byte)).collect();
+            format!("{:04X}  {}", start + i * 16, bytes.join(" "))
+        })
+        .collect();
+
+    lines.join("\n")
+}
+
+/// `count` instructions centred on the program counter, which is marked
+pub fn disassemble_around(emu: &Emulator, count: usize) -> String {
+    let first = emu.program_counter.saturating_sub(count / 2 * 2);
+    let mut lines = Vec::new();
+
+    for i in 0..count {
+        let address = first + i * 2;
+        let word = |address: usize| match (emu.memory.get(address), emu.memory.get(address + 1)) {
+            (Some(high), Some(low)) => Some((*high as u16) << 8 | *low as u16),
+            _ => None
+        };
+
+        let opcode = match word(address) {
+            Some(opcode) => opcode,
+            None => break
+        };
+        let text = disassembler::decode(opcode, word(address + 2))
+            .unwrap_or_else(|| format!("DW 0x{:04X}", opcode));
+        let marker = if address == emu.program_counter { ">" } else { " " };
+
+        lines.push(format!("{} {:03X}  {:04X}  {}", marker, address, opcode, text));
+    }
+
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+    use crate::emulator::Quirks;
+
+    fn program(source: &str) -> Emulator {
+        Emulator::load_bytes(&assemble(source).unwrap(), Quirks::default()).unwrap()
+    }
+
+    #[test]
+    fn parse_commands() {
+        assert_eq!(Ok(Command::Step(1)), Command::parse("step"));
+        assert_eq!(Ok(Command::Step(10)), Command::parse("s 10"));
+        assert_eq!(Ok(Command::Break(Breakpoint::Address(0x20A))), Command::parse("break 0x20A"));
+        assert_eq!(
+            Ok(Command::Break(Breakpoint::Opcode { value: 0xD005, mask: 0xF00F })),
+            Command::parse("b op D..5")
+        );
+        assert_eq!(Ok(Command::Memory { start: 0x200, length: 64 }), Command::parse("memory 0x200"));
+        // Addresses are typed back as the debugger shows them
+        assert_eq!(Ok(Command::Break(Breakpoint::Address(0x20A))), Command::parse("break 20A"));
+        assert_eq!(Ok(Command::Break(Breakpoint::Address(0x200))), Command::parse("b 200"));
+        assert_eq!(Ok(Command::Memory { start: 0x300, length: 16 }), Command::parse("m 300 16"));
+        assert!(Command::parse("break 20G").is_err());
+        assert!(Command::parse("b op D5").is_err());
+        assert!(Command::parse("jump").is_err());
+    }
+
+    #[test]
+    fn step_stops_at_breakpoints() {
+        let mut emu = program("LD V0, 1\nLD V1, 2\nDRW V0, V1, 5\nLD V2, 3");
+        let mut debugger = Debugger::new(10);
+        debugger.add_breakpoint(Breakpoint::opcode("D..5").unwrap());
+
+        assert_eq!(Ok(Stop::Breakpoint(0)), debugger.step(&mut emu, 10));
+        assert_eq!(0x204, emu.program_counter);
+
+        // Stepping again runs the instruction that hit the breakpoint
+        assert_eq!(Ok(Stop::Done), debugger.step(&mut emu, 2));
+        assert_eq!(3, emu.registers()[2]);
+    }
+
+    #[test]
+    fn timers_tick_once_a_frame() {
+        let mut emu = program("LD V0, 5\nLD DT, V0\nLD V1, 1\nLD V1, 2\nLD V1, 3\nLD V1, 4");
+        let mut debugger = Debugger::new(3);
+
+        // The first frame ticks before the timer is set, however long the
+        // prompt waits between steps
+        debugger.step(&mut emu, 2).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        debugger.step(&mut emu, 1).unwrap();
+        assert_eq!(5, emu.delay_timer());
+        assert!(debugger.is_frame_finished());
+
+        debugger.step(&mut emu, 3).unwrap();
+        assert_eq!(4, emu.delay_timer());
+        assert_eq!(6, debugger.cycle());
+    }
+
+    #[test]
+    fn failed_instructions_are_not_counted() {
+        let mut emu = program("LD V0, 5\nLD DT, V0");
+        let mut debugger = Debugger::new(2);
+        debugger.step(&mut emu, 2).unwrap();
+
+        // An unknown opcode starting the next frame, retried after failing
+        emu.memory[emu.program_counter] = 0xF0;
+        emu.memory[emu.program_counter + 1] = 0xFF;
+        assert!(debugger.run_instruction(&mut emu).is_err());
+        assert!(debugger.run_instruction(&mut emu).is_err());
+
+        assert_eq!(2, debugger.cycle());
+        assert_eq!(4, emu.delay_timer());
+    }
+
+    #[test]
+    fn views() {
+        let mut emu = program("LD V0, 0xAB\nLD I, 0x300\nCLS");
+        emu.step().unwrap();
+
+        assert!(registers(&emu).starts_with("V0=AB V1=00"));
+        assert_eq!("0200  60 AB A3", hexdump(&emu, 0x200, 3));
+        // Lengths past the end of memory stop at the end
+        let last = emu.memory.len() - 16;
+        assert_eq!(hexdump(&emu, last, 16), hexdump(&emu, last, usize::MAX));
+        assert_eq!(
+            "  200  60AB  LD V0, 0xAB\n> 202  A300  LD I, 0x300\n  204  00E0  CLS",
+            disassemble_around(&emu, 3)
+        );
+    }
+}
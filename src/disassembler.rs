@@ -0,0 +1,308 @@
+use std::collections::{BTreeSet, HashSet};
+use std::fmt;
+
+/// One past the last address a program can be loaded at
+const MEMORY_END: usize = 0x10000;
+
+/// Whether a line of the listing was reached by following the program
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum LineKind {
+    Code,
+    /// Bytes that code flow never reaches, usually sprites or other data
+    Data
+}
+
+/// A single instruction or run of data in a disassembled program
+#[derive(Clone, PartialEq, Debug)]
+pub struct Line {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    /// Set when this line is the target of a jump or call
+    pub label: Option<String>,
+    pub text: String,
+    pub kind: LineKind
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let raw: String = self.bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+
+        if let Some(label) = &self.label {
+            writeln!(f, "{}:", label)?;
+        }
+
+        write!(f, "{:03X}  {:<8}  {}", self.address, raw, self.text)
+    }
+}
+
+/// Disassembles a program loaded at `origin`, following jumps, calls and
+/// skips from the start of the program to tell code from data
+pub fn disassemble(bytes: &[u8], origin: u16) -> Vec<Line> {
+    // Anything past the end of memory couldn't be loaded
+    let bytes = &bytes[..bytes.len().min(MEMORY_END - origin as usize)];
+    let starts = trace(bytes, origin);
+    let targets = jump_targets(bytes, origin, &starts);
+    let label = |address: u16| {
+        if targets.contains(&address) { Some(label_name(address)) } else { None }
+    };
+
+    let mut lines = Vec::new();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let address = origin + i as u16;
+
+        if starts.contains(&i) {
+            let opcode = word(bytes, i);
+            let size = instruction_size(opcode);
+            let long = if size == 4 { Some(word(bytes, i + 2)) } else { None };
+
+            lines.push(Line {
+                address,
+                bytes: bytes[i..i + size].to_vec(),
+                label: label(address),
+                // Only reachable, known opcodes are traced so this can't fail
+                text: mnemonic(opcode, long, &targets).unwrap_or_default(),
+                kind: LineKind::Code
+            });
+            i += size;
+        } else {
+            // Data runs two bytes at a time, stopping short of any code
+            let mut end = i + 1;
+            if end < bytes.len() && !starts.contains(&end) {
+                end += 1;
+            }
+
+            let data: Vec<String> = bytes[i..end].iter().map(|byte| format!("0x{:02X}", byte)).collect();
+            lines.push(Line {
+                address,
+                bytes: bytes[i..end].to_vec(),
+                label: label(address),
+                text: format!("DB {}", data.join(", ")),
+                kind: LineKind::Data
+            });
+            i = end;
+        }
+    }
+
+    lines
+}
+
+/// The name used for the label at `address`
+fn label_name(address: u16) -> String {
+    format!("L{:03X}", address)
+}
+
+/// The big endian word at `i`, with anything past the end read as zero
+fn word(bytes: &[u8], i: usize) -> u16 {
+    let high = *bytes.get(i).unwrap_or(&0) as u16;
+    let low = *bytes.get(i + 1).unwrap_or(&0) as u16;
+
+    high << 8 | low
+}
+
+/// F000 NNNN is the only instruction with an operand after it
+fn instruction_size(opcode: u16) -> usize {
+    if opcode == 0xF000 { 4 } else { 2 }
+}
+
+/// Finds the offsets of every instruction reachable from the start
+fn trace(bytes: &[u8], origin: u16) -> HashSet<usize> {
+    let mut starts = HashSet::new();
+    let mut pending = vec![origin as usize];
+
+    while let Some(address) = pending.pop() {
+        let i = match address.checked_sub(origin as usize) {
+            Some(i) if i + 1 < bytes.len() => i,
+            _ => continue
+        };
+
+        if starts.contains(&i) {
+            continue;
+        }
+
+        let opcode = word(bytes, i);
+        let size = instruction_size(opcode);
+        if i + size > bytes.len() || mnemonic(opcode, Some(0), &BTreeSet::new()).is_none() {
+            continue;
+        }
+
+        starts.insert(i);
+        let next = address + size;
+        let nnn = (opcode & 0x0FFF) as usize;
+
+        match opcode >> 12 {
+            // Returning and exiting end this path
+            0x0 if opcode == 0x00EE || opcode == 0x00FD => (),
+            0x1 => pending.push(nnn),
+            0x2 => {
+                pending.push(nnn);
+                pending.push(next);
+            },
+            // The destination of a computed jump can't be known
+            0xB => (),
+            0x5 if opcode & 0xF != 0 => pending.push(next),
+            0x3 | 0x4 | 0x5 | 0x9 | 0xE => {
+                let skipped = instruction_size(word(bytes, i + size));
+                pending.push(next);
+                pending.push(next + skipped);
+            },
+            _ => pending.push(next)
+        }
+    }
+
+    starts
+}
+
+/// The addresses of instructions jumped to or called by reachable code
+fn jump_targets(bytes: &[u8], origin: u16, starts: &HashSet<usize>) -> BTreeSet<u16> {
+    starts
+        .iter()
+        .map(|i| word(bytes, *i))
+        .filter(|opcode| matches!(opcode >> 12, 0x1 | 0x2 | 0xB))
+        .map(|opcode| opcode & 0x0FFF)
+        .filter(|target| {
+            (*target as usize).checked_sub(origin as usize).is_some_and(|i| starts.contains(&i))
+        })
+        .collect()
+}
+
+/// The mnemonic for a single opcode, or `None` if it isn't an instruction.
+/// `long` is the word after it, which is the address for an F000 NNNN.
+pub fn decode(opcode: u16, long: Option<u16>) -> Option<String> {
+    mnemonic(opcode, long, &BTreeSet::new())
+}
+
+/// The mnemonic for an opcode, `long` is the word after an F000 NNNN.
+/// Jumps and calls to an address in `labels` use the label name.
+fn mnemonic(opcode: u16, long: Option<u16>, labels: &BTreeSet<u16>) -> Option<String> {
+    let x = (opcode >> 8) & 0xF;
+    let y = (opcode >> 4) & 0xF;
+    let n = opcode & 0xF;
+    let nn = opcode & 0xFF;
+    let nnn = opcode & 0xFFF;
+    let target = if labels.contains(&nnn) { label_name(nnn) } else { format!("0x{:03X}", nnn) };
+
+    let text = match opcode >> 12 {
+        0x0 => match nnn {
+            0x0E0 => "CLS".to_string(),
+            0x0EE => "RET".to_string(),
+            0x0FB => "SCR".to_string(),
+            0x0FC => "SCL".to_string(),
+            0x0FD => "EXIT".to_string(),
+            0x0FE => "LOW".to_string(),
+            0x0FF => "HIGH".to_string(),
+            _ if nnn & 0xFF0 == 0x0C0 => format!("SCD {}", n),
+            _ if nnn & 0xFF0 == 0x0D0 => format!("SCU {}", n),
+            _ => return None
+        },
+        0x1 => format!("JP {}", target),
+        0x2 => format!("CALL {}", target),
+        0x3 => format!("SE V{:X}, 0x{:02X}", x, nn),
+        0x4 => format!("SNE V{:X}, 0x{:02X}", x, nn),
+        0x5 => match n {
+            0x0 => format!("SE V{:X}, V{:X}", x, y),
+            0x2 => format!("LD [I], V{:X}-V{:X}", x, y),
+            0x3 => format!("LD V{:X}-V{:X}, [I]", x, y),
+            _ => return None
+        },
+        0x6 => format!("LD V{:X}, 0x{:02X}", x, nn),
+        0x7 => format!("ADD V{:X}, 0x{:02X}", x, nn),
+        0x8 => {
+            let operation = match n {
+                0x0 => "LD",
+                0x1 => "OR",
+                0x2 => "AND",
+                0x3 => "XOR",
+                0x4 => "ADD",
+                0x5 => "SUB",
+                0x6 => "SHR",
+                0x7 => "SUBN",
+                0xE => "SHL",
+                _ => return None
+            };
+            format!("{} V{:X}, V{:X}", operation, x, y)
+        },
+        0x9 if n == 0 => format!("SNE V{:X}, V{:X}", x, y),
+        0xA => format!("LD I, 0x{:03X}", nnn),
+        0xB => format!("JP V0, {}", target),
+        0xC => format!("RND V{:X}, 0x{:02X}", x, nn),
+        0xD => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        0xE => match nn {
+            0x9E => format!("SKP V{:X}", x),
+            0xA1 => format!("SKNP V{:X}", x),
+            _ => return None
+        },
+        0xF => match nn {
+            0x00 if x == 0 => format!("LD I, LONG 0x{:04X}", long?),
+            0x01 => format!("PLANE {}", x),
+            0x02 if x == 0 => "AUDIO".to_string(),
+            0x07 => format!("LD V{:X}, DT", x),
+            0x0A => format!("LD V{:X}, K", x),
+            0x15 => format!("LD DT, V{:X}", x),
+            0x18 => format!("LD ST, V{:X}", x),
+            0x1E => format!("ADD I, V{:X}", x),
+            0x29 => format!("LD F, V{:X}", x),
+            0x30 => format!("LD HF, V{:X}", x),
+            0x33 => format!("LD B, V{:X}", x),
+            0x3A => format!("PITCH V{:X}", x),
+            0x55 => format!("LD [I], V{:X}", x),
+            0x65 => format!("LD V{:X}, [I]", x),
+            0x75 => format!("LD R, V{:X}", x),
+            0x85 => format!("LD V{:X}, R", x),
+            _ => return None
+        },
+        _ => return None
+    };
+
+    Some(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mnemonics() {
+        let lines = disassemble(&[0xD0, 0x15, 0xA2, 0xA0, 0xF0, 0x00, 0x12, 0x34], 0x200);
+        let text: Vec<&str> = lines.iter().map(|line| line.text.as_str()).collect();
+
+        assert_eq!(vec!["DRW V0, V1, 5", "LD I, 0x2A0", "LD I, LONG 0x1234"], text);
+    }
+
+    #[test]
+    fn labels_jump_targets() {
+        // Call a subroutine that returns, then loop forever
+        let rom = [0x22, 0x04, 0x12, 0x02, 0x00, 0xEE];
+        let lines = disassemble(&rom, 0x200);
+
+        assert_eq!("CALL L204", lines[0].text);
+        assert_eq!(Some("L202".to_string()), lines[1].label);
+        assert_eq!("JP L202", lines[1].text);
+        assert_eq!(Some("L204".to_string()), lines[2].label);
+        assert_eq!("RET", lines[2].text);
+    }
+
+    #[test]
+    fn unreached_bytes_are_data() {
+        // Skip over a sprite that is never executed
+        let rom = [0x12, 0x04, 0xF0, 0x90, 0xA2, 0x02, 0x12, 0x06];
+        let lines = disassemble(&rom, 0x200);
+
+        assert_eq!(LineKind::Data, lines[1].kind);
+        assert_eq!("DB 0xF0, 0x90", lines[1].text);
+        assert_eq!(LineKind::Code, lines[2].kind);
+        assert_eq!("204  A202      LD I, 0x202", lines[2].to_string().lines().last().unwrap());
+    }
+
+    #[test]
+    fn stops_at_the_end_of_memory() {
+        // Skips and plain instructions running into the last address
+        let rom = vec![0x30; MEMORY_END - 0x200 + 4];
+        let lines = disassemble(&rom, 0x200);
+
+        let last = lines.last().unwrap();
+        assert_eq!(0xFFFE, last.address);
+        assert_eq!("SE V0, 0x30", last.text);
+    }
+}
@@ -1,4 +1,13 @@
-mod opcodes;
+pub mod error;
+pub mod opcodes;
+pub mod quirks;
+mod rng;
+mod state;
+
+pub use self::error::Chip8Error;
+pub use self::quirks::Quirks;
+
+use self::rng::Rng;
 
 use std::time::{Duration, Instant};
 use std::collections::HashMap;
@@ -6,10 +15,12 @@ use std::fs::File;
 use std::io::Read;
 use std::ops::Not;
 
+// The timers count down at 60Hz
+const TIME_STEP: Duration = Duration::from_nanos(1_000_000_000 / 60);
 // Where the program starts in memory
-const TIME_STEP_SECONDS: f32 = 1f32/60f32;
 const PROGRAM_LOC: usize = 0x200;
 const FONTSET_LOC: u16 = 0x050;
+const BIG_FONTSET_LOC: u16 = FONTSET_LOC + FONTSET.len() as u16;
 const FONTSET: [u8; 5 * 16] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
     0x20, 0x60, 0x20, 0x20, 0x70, // 1
@@ -28,19 +39,50 @@ const FONTSET: [u8; 5 * 16] = [
     0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
     0xF0, 0x80, 0xF0, 0x80, 0x80  // F
 ];
+// The SUPER-CHIP 8x10 font, extended with the A-F digits
+const BIG_FONTSET: [u8; 10 * 16] = [
+    0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+    0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+    0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+    0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+    0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+    0x3E, 0x7C, 0xC0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+    0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0  // F
+];
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Copy, Clone, PartialEq, Debug)]
 pub enum Pixel {
     ON,
     OFF
 }
 
-#[derive(Debug)]
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub enum KeyState {
     DOWN,
     UP
 }
 
+/// How far an FX0A has got waiting for a key to be pressed and released
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum KeyWait {
+    Idle,
+    Waiting,
+    Pressed(u8),
+    /// The key to store once the FX0A runs again
+    Released(u8)
+}
+
 impl Not for Pixel {
     type Output = Pixel;
 
@@ -53,47 +95,69 @@ impl Not for Pixel {
 }
 
 pub struct Emulator {
-    pub memory: [u8; 4096],
+    pub memory: [u8; Emulator::MEMORY_SIZE],
     registers: [u8; 16],
     index_register: u16,
     pub program_counter: usize,
-    graphics: [Pixel; Emulator::SCREEN_SIZE],
+    // The colour of each pixel, with a bit for each XO-CHIP plane it is set on
+    graphics: [u8; Emulator::HIRES_SCREEN_SIZE],
+    planes: u8,
+    hires: bool,
+    exited: bool,
+    rpl_flags: [u8; 16],
+    audio_pattern: Option<[u8; 16]>,
+    pitch: u8,
     delay_timer: u8,
     sound_timer: u8,
     stack: [u16; 16],
     stack_pointer: usize,
     keys: HashMap<u8, KeyState>,
-    number_of_keys_pressed: i32,
-    last_key_pressed: u8,
+    key_wait: KeyWait,
     pub draw: bool,
     pub clear: bool,
     last_cycle_time: Option<Instant>,
-    hz_counter: Duration
+    hz_counter: Duration,
+    quirks: Quirks,
+    rng: Rng
 }
 
 impl Emulator {
+    /// 64 KiB as on XO-CHIP, of which the classic programs only use 4 KiB
+    pub const MEMORY_SIZE: usize = 0x10000;
+    /// Where programs are loaded in memory
+    pub const PROGRAM_START: usize = PROGRAM_LOC;
     pub const SCREEN_WIDTH: u16 = 64;
     pub const SCREEN_HEIGHT: u16 = 32;
-    const SCREEN_SIZE: usize = (Emulator::SCREEN_WIDTH * Emulator::SCREEN_HEIGHT) as usize;
+    pub const HIRES_SCREEN_WIDTH: u16 = 128;
+    pub const HIRES_SCREEN_HEIGHT: u16 = 64;
+    const HIRES_SCREEN_SIZE: usize =
+        (Emulator::HIRES_SCREEN_WIDTH * Emulator::HIRES_SCREEN_HEIGHT) as usize;
 
-    pub fn new() -> Emulator {
+    pub fn new(quirks: Quirks) -> Emulator {
         let mut emu = Emulator {
-            memory: [0; 4096],
+            memory: [0; Emulator::MEMORY_SIZE],
             registers: [0; 16],
             index_register: 0,
             program_counter: PROGRAM_LOC,
-            graphics: [Pixel::OFF; Emulator::SCREEN_SIZE],
+            graphics: [0; Emulator::HIRES_SCREEN_SIZE],
+            planes: 1,
+            hires: false,
+            exited: false,
+            rpl_flags: [0; 16],
+            audio_pattern: None,
+            pitch: 64,
             delay_timer: 0,
             sound_timer: 0,
             stack: [0; 16],
             stack_pointer: 0,
             keys: HashMap::new(),
-            number_of_keys_pressed: 0,
-            last_key_pressed: 0,
+            key_wait: KeyWait::Idle,
             draw: false,
             clear: false,
             last_cycle_time: None,
-            hz_counter: Duration::new(0, 0)
+            hz_counter: Duration::new(0, 0),
+            quirks,
+            rng: Rng::default()
         };
 
         // Insert all the keys as currently unpressed
@@ -102,57 +166,137 @@ impl Emulator {
         }
 
         // Install the fontset in memory
-        for i in 0..FONTSET.len() {
-            emu.memory[FONTSET_LOC as usize + i] = FONTSET[i];
-        }
+        let fontset_loc = FONTSET_LOC as usize;
+        emu.memory[fontset_loc..fontset_loc + FONTSET.len()].copy_from_slice(&FONTSET);
+
+        let big_fontset_loc = BIG_FONTSET_LOC as usize;
+        emu.memory[big_fontset_loc..big_fontset_loc + BIG_FONTSET.len()]
+            .copy_from_slice(&BIG_FONTSET);
 
         emu
     }
 
-    pub fn load(path: &str) -> Emulator {
-        let mut file = File::open(path).unwrap();
-        let mut buf = [0u8; 4096];
+    /// Creates an emulator with the rom at `path` loaded into memory
+    pub fn load(path: &str, quirks: Quirks) -> Result<Emulator, Chip8Error> {
+        let mut rom = Vec::new();
+
+        File::open(path)
+            .and_then(|mut file| file.read_to_end(&mut rom))
+            .map_err(|_| Chip8Error::RomNotFound(path.to_string()))?;
 
-        file.read(&mut buf[512..]).unwrap();
+        Emulator::load_bytes(&rom, quirks)
+    }
 
-        let mut emu = Emulator::new();
-        emu.memory = buf;
+    /// Creates an emulator with `rom` loaded into memory at the program start
+    pub fn load_bytes(rom: &[u8], quirks: Quirks) -> Result<Emulator, Chip8Error> {
+        let mut emu = Emulator::new(quirks);
+        let max = emu.memory.len() - PROGRAM_LOC;
 
-        emu
+        if rom.len() > max {
+            return Err(Chip8Error::RomTooLarge { size: rom.len(), max });
+        }
+
+        emu.memory[PROGRAM_LOC..PROGRAM_LOC + rom.len()].copy_from_slice(rom);
+
+        Ok(emu)
     }
 
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    /// Seeds the random numbers given by CXNN, so that runs can be repeated
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.rng = Rng::new(seed);
+    }
+
+    /// The width of the display in the current resolution
+    pub fn screen_width(&self) -> u16 {
+        if self.hires { Emulator::HIRES_SCREEN_WIDTH } else { Emulator::SCREEN_WIDTH }
+    }
+
+    /// The height of the display in the current resolution
+    pub fn screen_height(&self) -> u16 {
+        if self.hires { Emulator::HIRES_SCREEN_HEIGHT } else { Emulator::SCREEN_HEIGHT }
+    }
+
+    /// Whether the program has switched to the SUPER-CHIP 128x64 mode
+    pub fn is_hires(&self) -> bool {
+        self.hires
+    }
+
+    /// The general purpose registers V0 to VF
+    pub fn registers(&self) -> &[u8; 16] {
+        &self.registers
+    }
+
+    pub fn index_register(&self) -> u16 {
+        self.index_register
+    }
+
+    /// The return addresses of the subroutines currently being executed
+    pub fn stack(&self) -> &[u16] {
+        &self.stack[..self.stack_pointer]
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    /// Whether the program has stopped the interpreter with 00FD
+    pub fn has_exited(&self) -> bool {
+        self.exited
+    }
+
+    /// Whether a pixel is lit on any plane
     pub fn get_pixel(&self, x: u16, y: u16) -> Pixel {
-       self.graphics[((y * Emulator::SCREEN_WIDTH) + x) as usize]
+        match self.get_colour(x, y) {
+            0 => Pixel::OFF,
+            _ => Pixel::ON
+        }
+    }
+
+    /// The colour of a pixel from 0 to 3, bit 0 is set when it is lit on the
+    /// first plane and bit 1 when it is lit on the second XO-CHIP plane
+    pub fn get_colour(&self, x: u16, y: u16) -> u8 {
+        self.graphics[((y * self.screen_width()) + x) as usize]
+    }
+
+    /// The XO-CHIP audio pattern, if the program has loaded one. Each bit is
+    /// one sample, played from the most significant bit of the first byte.
+    pub fn audio_pattern(&self) -> Option<&[u8; 16]> {
+        self.audio_pattern.as_ref()
+    }
+
+    /// The rate in Hz that the audio pattern is played at, set with FX3A
+    pub fn audio_playback_rate(&self) -> f32 {
+        4000.0 * 2f32.powf((self.pitch as f32 - 64.0) / 48.0)
     }
 
     fn handle_timers(&mut self, delta: Option<Duration>) {
 
         // Handle timers
-        match self.last_cycle_time {
+        let delta = match (delta, self.last_cycle_time) {
+            (Some(delta), _) => Some(delta),
+            (None, Some(time)) => Some(time.elapsed()),
             // This is the first emulated cycle, nothing to do here
-            None => (),
-            Some(time) => {
-                let delta = match delta {
-                    Some(delta) => delta,
-                    None => time.elapsed()
-                };
+            (None, None) => None
+        };
 
+        match delta {
+            None => (),
+            Some(delta) => {
                 self.hz_counter += delta;
 
-                if self.hz_counter > Duration::from_secs_f32(TIME_STEP_SECONDS) {
-                    self.hz_counter = Duration::new(0, 0);
-
-                    if self.delay_timer > 0 {
-                        self.delay_timer -= 1;
-                    }
-
-                    if self.sound_timer > 0 {
-                        self.sound_timer -= 1;
-
-                        if self.sound_timer == 1 {
-                            println!("Ping!");
-                        }
-                    }
+                if self.hz_counter >= TIME_STEP {
+                    // Keep the remainder so the timers don't drift, but don't
+                    // let a long pause build up ticks to catch up on
+                    self.hz_counter = (self.hz_counter - TIME_STEP).min(TIME_STEP);
+                    self.tick_timers();
                 }
             }
         };
@@ -160,20 +304,87 @@ impl Emulator {
         self.last_cycle_time = Some(Instant::now());
     }
 
+    /// Counts the delay and sound timers down by one 60Hz tick
+    pub fn tick_timers(&mut self) {
+        if self.delay_timer > 0 {
+            self.delay_timer -= 1;
+        }
+
+        if self.sound_timer > 0 {
+            self.sound_timer -= 1;
+        }
+    }
+
+    /// Whether the beeper should sound, which it does while the sound timer
+    /// is counting down
+    pub fn is_sound_active(&self) -> bool {
+        self.sound_timer > 0
+    }
+
+    /// Emulates one 60Hz frame, ticking the timers once and then running up
+    /// to `cycles_per_frame` instructions. It stops early if the program
+    /// exits. `draw` and `clear` are set if any instruction set them.
+    ///
+    /// Ticking first means a timer set to N is non-zero at the end of exactly
+    /// N frames, so the beeper sounds for as long as it should.
+    pub fn run_frame(&mut self, cycles_per_frame: usize) -> Result<(), Chip8Error> {
+        let mut draw = false;
+        let mut clear = false;
+
+        self.tick_timers();
+
+        for _ in 0..cycles_per_frame {
+            if self.exited {
+                break;
+            }
+
+            let result = self.step();
+            draw |= self.draw;
+            clear |= self.clear;
+            result?;
+        }
+
+        self.draw = draw;
+        self.clear = clear;
+        Ok(())
+    }
+
+    /// Emulates a cycle of the emulator, timing it with an internal timer
+    pub fn emulate_cycle(&mut self) -> Result<(), Chip8Error> {
+        self.cycle(None)
+    }
+
     /// Emulates a cycle of the emulator
     ///
     /// # Arguments
     ///
-    /// * `delta` - The time since this emulator was last called in milliseconds,
-    /// if not provided an internal timer will be used
-    pub fn emulate_cycle(&mut self) {
-        self.handle_timers(None);
+    /// * `delta` - The time since this emulator was last called, used instead
+    ///   of the internal timer so that runs can be reproduced exactly
+    pub fn emulate_cycle_with_delta(&mut self, delta: Duration) -> Result<(), Chip8Error> {
+        self.cycle(Some(delta))
+    }
+
+    fn cycle(&mut self, delta: Option<Duration>) -> Result<(), Chip8Error> {
+        if self.exited {
+            return Ok(());
+        }
+
+        self.handle_timers(delta);
+        self.step()
+    }
+
+    /// Runs the instruction at the program counter without touching the
+    /// timers, for frontends which tick them with `tick_timers`
+    pub fn step(&mut self) -> Result<(), Chip8Error> {
+        if self.exited {
+            return Ok(());
+        }
 
         // Reset the drawing an clearing flags
         self.clear = false;
         self.draw = false;
 
-        let opcode = self.get_opcode();
+        let opcode = self.get_opcode()?;
         let (instruction, value) = Emulator::deconstruct_opcode(opcode);
 
         //println!("{} {:X}", self.program_counter, opcode);
@@ -184,7 +395,7 @@ impl Emulator {
             0x2 => opcodes::call_subroutine,
             0x3 => opcodes::skip_true,
             0x4 => opcodes::skip_false,
-            0x5 => opcodes::skip_equals,
+            0x5 => opcodes::register_ops,
             0x6 => opcodes::set_register,
             0x7 => opcodes::add_to_register,
             0x8 => opcodes::maths_ops,
@@ -195,30 +406,29 @@ impl Emulator {
             0xD => opcodes::draw,
             0xE => opcodes::skip_pressed,
             0xF => opcodes::misc_opcodes,
-            _   => panic!("Missed opcoded {:x}", opcode)
+            _   => return Err(self.unknown_opcode(opcode))
         };
 
-        run(self, value);
+        run(self, value)
     }
 
     pub fn set_key(&mut self, key: u8, state: KeyState) {
-        match state {
-            KeyState::UP => self.number_of_keys_pressed -= 1,
-            KeyState::DOWN => {
-                self.number_of_keys_pressed += 1;
-                self.last_key_pressed = key;
-            }
+        // Like the VIP, FX0A takes the key when it is released
+        self.key_wait = match (self.key_wait, state) {
+            (KeyWait::Waiting, KeyState::DOWN) => KeyWait::Pressed(key),
+            (KeyWait::Pressed(pressed), KeyState::UP) if pressed == key => KeyWait::Released(key),
+            (key_wait, _) => key_wait
         };
 
-        // Should prevent negative keys being pressed if a key was pressed
-        // before the program was started
-        if self.number_of_keys_pressed < 0 {
-            self.number_of_keys_pressed = 0;
-        }
-
         self.keys.insert(key, state);
     }
 
+    /// Whether the program is stopped on an FX0A until a key is pressed and
+    /// released. The timers keep running in the meantime.
+    pub fn is_waiting_for_key(&self) -> bool {
+        self.key_wait != KeyWait::Idle
+    }
+
     pub fn get_key(&self, key: u8) -> KeyState {
         match self.keys.get(&key) {
             Some(KeyState::DOWN) => KeyState::DOWN,
@@ -226,9 +436,40 @@ impl Emulator {
         }
     }
 
-    fn get_opcode(&self) -> u16 {
-        (self.memory[self.program_counter] as u16) << 8
-            | self.memory[self.program_counter + 1] as u16
+    /// The opcode at the program counter, which will be run next
+    pub fn peek_opcode(&self) -> Result<u16, Chip8Error> {
+        self.get_opcode()
+    }
+
+    fn get_opcode(&self) -> Result<u16, Chip8Error> {
+        Ok((self.read_memory(self.program_counter)? as u16) << 8
+            | self.read_memory(self.program_counter + 1)? as u16)
+    }
+
+    /// Reads a byte of memory, failing if the address is past the end of RAM
+    fn read_memory(&self, address: usize) -> Result<u8, Chip8Error> {
+        match self.memory.get(address) {
+            Some(byte) => Ok(*byte),
+            None => Err(Chip8Error::MemoryOutOfBounds { pc: self.program_counter, address })
+        }
+    }
+
+    /// Writes a byte of memory, failing if the address is past the end of RAM
+    fn write_memory(&mut self, address: usize, value: u8) -> Result<(), Chip8Error> {
+        let pc = self.program_counter;
+
+        match self.memory.get_mut(address) {
+            Some(byte) => {
+                *byte = value;
+                Ok(())
+            },
+            None => Err(Chip8Error::MemoryOutOfBounds { pc, address })
+        }
+    }
+
+    /// The error for the opcode at the current program counter
+    fn unknown_opcode(&self, opcode: u16) -> Chip8Error {
+        Chip8Error::UnknownOpcode { pc: self.program_counter, opcode }
     }
 
     fn deconstruct_opcode(opcode: u16) -> (u8, u16) {
@@ -236,18 +477,116 @@ impl Emulator {
     }
 }
 
-#[cfg(red)]
+impl Default for Emulator {
+    fn default() -> Self {
+        Emulator::new(Quirks::default())
+    }
+}
+
+#[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn set_index_register() {
         let new_index_reg = 0x210;
-        let mut emu = Emulator::new();
+        let mut emu = Emulator::default();
         emu.memory[emu.program_counter] = 0xA2;
         emu.memory[emu.program_counter + 1] = 0x10;
 
-        let emu = emu.emulate_cycle();
+        emu.emulate_cycle().unwrap();
         assert_eq!(emu.index_register, new_index_reg);
     }
+
+    #[test]
+    fn load_bytes() {
+        let emu = Emulator::load_bytes(&[0x12, 0x00], Quirks::default()).unwrap();
+
+        assert_eq!(0x12, emu.memory[PROGRAM_LOC]);
+        assert_eq!(FONTSET[0], emu.memory[FONTSET_LOC as usize]);
+    }
+
+    #[test]
+    fn rom_too_large() {
+        let rom = vec![0u8; Emulator::MEMORY_SIZE];
+
+        assert_eq!(
+            Err(Chip8Error::RomTooLarge {
+                size: Emulator::MEMORY_SIZE,
+                max: Emulator::MEMORY_SIZE - PROGRAM_LOC
+            }),
+            Emulator::load_bytes(&rom, Quirks::default()).map(|_| ())
+        );
+    }
+
+    #[test]
+    fn rom_not_found() {
+        assert_eq!(
+            Err(Chip8Error::RomNotFound("data/missing.ch8".to_string())),
+            Emulator::load("data/missing.ch8", Quirks::default()).map(|_| ())
+        );
+    }
+
+    #[test]
+    fn unknown_opcode() {
+        let mut emu = Emulator::load_bytes(&[0x01, 0x23], Quirks::default()).unwrap();
+
+        assert_eq!(
+            Err(Chip8Error::UnknownOpcode { pc: PROGRAM_LOC, opcode: 0x0123 }),
+            emu.emulate_cycle()
+        );
+        assert_eq!(PROGRAM_LOC, emu.program_counter);
+    }
+
+    #[test]
+    fn program_counter_out_of_bounds() {
+        let mut emu = Emulator::new(Quirks::default());
+        emu.program_counter = Emulator::MEMORY_SIZE - 1;
+
+        assert_eq!(
+            Err(Chip8Error::MemoryOutOfBounds {
+                pc: Emulator::MEMORY_SIZE - 1,
+                address: Emulator::MEMORY_SIZE
+            }),
+            emu.emulate_cycle()
+        );
+    }
+
+    #[test]
+    fn timers_keep_the_remainder() {
+        let mut emu = Emulator::default();
+        let pc = emu.program_counter;
+
+        // Loop forever
+        emu.memory[pc] = 0x12;
+        emu.memory[pc + 1] = 0x00;
+        emu.delay_timer = 10;
+
+        // 50ms is three ticks of the 60Hz timers
+        for _ in 0..5 {
+            emu.emulate_cycle_with_delta(Duration::from_millis(10)).unwrap();
+        }
+
+        assert_eq!(7, emu.delay_timer());
+    }
+
+    #[test]
+    fn run_frame() {
+        let mut emu = Emulator::load_bytes(&[
+            0x60, 0x0A, // V0 = 10
+            0xF0, 0x15, // DT = V0
+            0xD0, 0x01, // Draw at V0, V0
+            0x12, 0x06  // Loop forever
+        ], Quirks::default()).unwrap();
+
+        emu.run_frame(5).unwrap();
+        assert_eq!(10, emu.delay_timer());
+        assert!(emu.draw);
+
+        // The timers tick once per frame however many instructions are run
+        emu.run_frame(100).unwrap();
+        emu.run_frame(1).unwrap();
+        assert_eq!(8, emu.delay_timer());
+        assert!(!emu.draw);
+    }
 }
@@ -0,0 +1,43 @@
+use std::error::Error;
+use std::fmt;
+
+/// Everything that can go wrong while loading or running a Chip8 program
+#[derive(Debug, PartialEq)]
+pub enum Chip8Error {
+    /// The ROM at the given path could not be opened or read
+    RomNotFound(String),
+    /// The ROM does not fit in the memory available to programs
+    RomTooLarge { size: usize, max: usize },
+    /// The opcode at `pc` is not one the interpreter understands
+    UnknownOpcode { pc: usize, opcode: u16 },
+    /// A subroutine was called with all 16 stack slots in use
+    StackOverflow { pc: usize },
+    /// A return was executed with nothing on the stack
+    StackUnderflow { pc: usize },
+    /// The instruction at `pc` tried to access memory past the end of RAM
+    MemoryOutOfBounds { pc: usize, address: usize },
+    /// A save state was corrupt or written by an incompatible version
+    InvalidState(String),
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Chip8Error::RomNotFound(path) => write!(f, "Could not read the rom at {}", path),
+            Chip8Error::RomTooLarge { size, max } => {
+                write!(f, "Rom is {} bytes but at most {} bytes can be loaded", size, max)
+            },
+            Chip8Error::UnknownOpcode { pc, opcode } => {
+                write!(f, "Unknown opcode {:04X} at {:03X}", opcode, pc)
+            },
+            Chip8Error::StackOverflow { pc } => write!(f, "Stack overflow at {:03X}", pc),
+            Chip8Error::StackUnderflow { pc } => write!(f, "Stack underflow at {:03X}", pc),
+            Chip8Error::MemoryOutOfBounds { pc, address } => {
+                write!(f, "Memory access out of bounds at {:03X} (address {:X})", pc, address)
+            },
+            Chip8Error::InvalidState(reason) => write!(f, "Could not load the save state, {}", reason)
+        }
+    }
+}
+
+impl Error for Chip8Error {}
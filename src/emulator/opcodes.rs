@@ -1,110 +1,192 @@
 use crate::emulator::*;
 
+/// The result of running a single opcode
+pub type OpResult = Result<(), Chip8Error>;
+
 /// Manages the 0x0FFF opcodes
-pub fn system(emu: &mut Emulator, value: u16) {
+pub fn system(emu: &mut Emulator, value: u16) -> OpResult {
     match value {
         // Return from subroutine
         0x0EE => return_from_subroutine(emu, value),
         0x0E0 => {
             emu.program_counter += 2;
-            emu.graphics = [Pixel::OFF; Emulator::SCREEN_SIZE];
-            emu.clear = true;
-            println!("Clearing the screen!");
+            emu.clear_screen();
+            Ok(())
         },
-        _ => panic!("No instruction for 0x0{:X}", value)
-    };
+        // 00CN, scroll the display down N lines
+        _ if value & 0xFF0 == 0x0C0 => {
+            emu.program_counter += 2;
+            emu.scroll(0, (value & 0x00F) as i32);
+            Ok(())
+        },
+        // 00DN, the XO-CHIP scroll up N lines
+        _ if value & 0xFF0 == 0x0D0 => {
+            emu.program_counter += 2;
+            emu.scroll(0, -((value & 0x00F) as i32));
+            Ok(())
+        },
+        0x0FB => {
+            emu.program_counter += 2;
+            emu.scroll(4, 0);
+            Ok(())
+        },
+        0x0FC => {
+            emu.program_counter += 2;
+            emu.scroll(-4, 0);
+            Ok(())
+        },
+        // Exit the interpreter, leaving the program counter where it is
+        0x0FD => {
+            emu.exited = true;
+            Ok(())
+        },
+        0x0FE | 0x0FF => {
+            emu.program_counter += 2;
+            emu.hires = value == 0x0FF;
+            emu.clear_screen();
+            Ok(())
+        },
+        _ => Err(emu.unknown_opcode(value))
+    }
 }
 
-fn return_from_subroutine(emu: &mut Emulator, _: u16) {
+fn return_from_subroutine(emu: &mut Emulator, _: u16) -> OpResult {
+    if emu.stack_pointer == 0 {
+        return Err(Chip8Error::StackUnderflow { pc: emu.program_counter });
+    }
+
     // The new stack pointer that contains the location of the code we are
     // going to jump back to
     emu.stack_pointer -= 1;
     emu.program_counter = (emu.stack[emu.stack_pointer] + 2) as usize;
+    Ok(())
 }
 
-pub fn goto(emu: &mut Emulator, value: u16) {
+pub fn goto(emu: &mut Emulator, value: u16) -> OpResult {
     emu.program_counter = value.into();
+    Ok(())
 }
 
-pub fn call_subroutine(emu: &mut Emulator, value: u16) {
+pub fn call_subroutine(emu: &mut Emulator, value: u16) -> OpResult {
+    if emu.stack_pointer == emu.stack.len() {
+        return Err(Chip8Error::StackOverflow { pc: emu.program_counter });
+    }
+
     emu.stack[emu.stack_pointer] = emu.program_counter as u16;
     emu.stack_pointer += 1;
     emu.program_counter = value.into();
+    Ok(())
 }
 
 /// Skips the next instruction if VX equals NN
-pub fn skip_true(emu: &mut Emulator, value: u16) {
+pub fn skip_true(emu: &mut Emulator, value: u16) -> OpResult {
     let reg_loc = (value >> 8) as usize;
     let expected_reg_value = (value & 0x0FF) as u8;
 
-    let should_skip = emu.registers[reg_loc] == expected_reg_value;
-    let pc_delta = if should_skip { 4 } else { 2 };
-
-    emu.program_counter = emu.program_counter + pc_delta;
+    emu.skip_if(emu.registers[reg_loc] == expected_reg_value)
 }
 
 /// Skips the next instruction if VX does not equal NN
-pub fn skip_false(emu: &mut Emulator, value: u16) {
+pub fn skip_false(emu: &mut Emulator, value: u16) -> OpResult {
     let reg_loc = (value >> 8) as usize;
     let expected_reg_value = (value & 0x0FF) as u8;
 
-    let should_skip = emu.registers[reg_loc] != expected_reg_value;
-    let pc_delta = if should_skip { 4 } else { 2 };
+    emu.skip_if(emu.registers[reg_loc] != expected_reg_value)
+}
+
+/// 5XY0, 5XY2 & 5XY3
+pub fn register_ops(emu: &mut Emulator, value: u16) -> OpResult {
+    match value & 0x00F {
+        0x0 => skip_equals(emu, value),
+        0x2 => save_register_range(emu, value),
+        0x3 => load_register_range(emu, value),
+        _ => Err(emu.unknown_opcode(0x5000 | value))
+    }
+}
 
-    emu.program_counter = emu.program_counter + pc_delta;
+/// The registers from VX to VY, in descending order if Y is less than X
+fn register_range(value: u16) -> Vec<usize> {
+    let x = (value >> 8) as usize;
+    let y = ((value & 0x0F0) >> 4) as usize;
+
+    if x <= y {
+        (x..=y).collect()
+    } else {
+        (y..=x).rev().collect()
+    }
+}
+
+/// Stores VX to VY in memory starting at I, leaving I unchanged
+fn save_register_range(emu: &mut Emulator, value: u16) -> OpResult {
+    for (offset, register) in register_range(value).into_iter().enumerate() {
+        emu.write_memory(emu.index_register as usize + offset, emu.registers[register])?;
+    }
+
+    emu.program_counter += 2;
+    Ok(())
+}
+
+/// Loads VX to VY from memory starting at I, leaving I unchanged
+fn load_register_range(emu: &mut Emulator, value: u16) -> OpResult {
+    for (offset, register) in register_range(value).into_iter().enumerate() {
+        emu.registers[register] = emu.read_memory(emu.index_register as usize + offset)?;
+    }
+
+    emu.program_counter += 2;
+    Ok(())
 }
 
 /// Skips the next instruction if VX equals VY
-pub fn skip_equals(emu: &mut Emulator, value: u16) {
-    skip_condition(|x, y| { x == y })(emu, value);
+pub fn skip_equals(emu: &mut Emulator, value: u16) -> OpResult {
+    skip_condition(|x, y| { x == y })(emu, value)
 }
 
 /// Skips the next instruction if VX does not equal VY
-pub fn skip_not_equals(emu: &mut Emulator, value: u16) {
-    skip_condition(|x, y| { x != y })(emu, value);
+pub fn skip_not_equals(emu: &mut Emulator, value: u16) -> OpResult {
+    skip_condition(|x, y| { x != y })(emu, value)
 }
 
-pub fn skip_condition(condition: fn(u8, u8) -> bool) -> Box<dyn Fn(&mut Emulator, u16)> {
+/// An opcode handler built at runtime, see `skip_condition`
+pub type Instruction = Box<dyn Fn(&mut Emulator, u16) -> OpResult>;
+
+pub fn skip_condition(condition: fn(u8, u8) -> bool) -> Instruction {
     Box::new(move |emu: &mut Emulator, value: u16| {
         // 5XY0
         let x = (value >> 8) as usize;
         let y = ((value & 0x0F0) >> 4) as usize;
 
-        let mut pc_inc = 2;
-
-        if condition(emu.registers[x], emu.registers[y]) {
-            pc_inc += 2;
-        }
-
-        emu.program_counter = emu.program_counter + pc_inc;
+        emu.skip_if(condition(emu.registers[x], emu.registers[y]))
     })
 }
 
 /// Instruction 6XNN, store the number NN in register VX
-pub fn set_register(emu: &mut Emulator, value: u16) {
+pub fn set_register(emu: &mut Emulator, value: u16) -> OpResult {
     let reg_loc = (value >> 8) as usize;
     let new_reg_value = (value & 0x0FF) as u8;
 
 
     emu.registers[reg_loc] = new_reg_value;
     emu.program_counter += 2;
+    Ok(())
 }
 
-pub fn add_to_register(emu: &mut Emulator, value: u16) {
+pub fn add_to_register(emu: &mut Emulator, value: u16) -> OpResult {
     let reg_loc = (value >> 8) as usize;
     let reg_inc = (value & 0x0FF) as u8;
 
     emu.registers[reg_loc] = addition_carry(emu.registers[reg_loc], reg_inc).0;
     emu.program_counter += 2;
+    Ok(())
 }
 
-pub fn set_index_register(emu: &mut Emulator, value: u16) {
+pub fn set_index_register(emu: &mut Emulator, value: u16) -> OpResult {
     emu.index_register = value;
     emu.program_counter += 2;
+    Ok(())
 }
 
 /// Various bitwise and mathmatical operations for 0x8***
-pub fn maths_ops(emu: &mut Emulator, value: u16) {
+pub fn maths_ops(emu: &mut Emulator, value: u16) -> OpResult {
     let secondary_instruction = (value & 0x00F) as u8;
     let ix = (value >> 8) as usize;
     let iy = ((value & 0x0F0) >> 4) as usize;
@@ -113,23 +195,30 @@ pub fn maths_ops(emu: &mut Emulator, value: u16) {
     let f = emu.registers[0xF];
     let y = emu.registers[iy];
 
+    // The logical operations reset VF on the original VIP
+    let logic_f = if emu.quirks.vf_reset { 0 } else { f };
+
+    // Which register gets shifted into VX
+    let shifted = if emu.quirks.shift_uses_vy { y } else { x };
+
     // These operations both set Vx and Vf
     let (x, f) = match secondary_instruction {
         0x0 => (y, f),
-        0x1 => (x | y, f),
-        0x2 => (x & y, f),
-        0x3 => (x ^ y, f),
+        0x1 => (x | y, logic_f),
+        0x2 => (x & y, logic_f),
+        0x3 => (x ^ y, logic_f),
         0x4 => addition_carry(x, y),
         0x5 => minus_carry(x, y),
-        0x6 => (x >> 1, x & 0b00000001),
+        0x6 => (shifted >> 1, shifted & 0b00000001),
         0x7 => minus_carry(y, x),
-        0xE => (x << 1, x >> 7),
-        _ => (x, f)
+        0xE => (shifted << 1, shifted >> 7),
+        _ => return Err(emu.unknown_opcode(0x8000 | value))
     };
 
     emu.registers[ix] = x;
     emu.registers[0xF] = f;
     emu.program_counter += 2;
+    Ok(())
 }
 
 /// Adds VY to VX. VF is set to 1 when there's a carry, and to 0 when there isn't.
@@ -154,56 +243,86 @@ fn minus_carry(x: u8, y: u8) -> (u8, u8) {
     (result as u8, 1)
 }
 
-/// BNNN
-pub fn goto_plus_register(emu: &mut Emulator, value: u16) {
-    emu.program_counter = value as usize + emu.registers[0] as usize;
+/// BNNN, or BXNN when jumping relative to VX
+pub fn goto_plus_register(emu: &mut Emulator, value: u16) -> OpResult {
+    let register = if emu.quirks.jump_uses_vx { value >> 8 } else { 0 };
+
+    emu.program_counter = value as usize + emu.registers[register as usize] as usize;
+    Ok(())
 }
 
 /// CXNN
-pub fn rand(emu: &mut Emulator, value: u16) {
+pub fn rand(emu: &mut Emulator, value: u16) -> OpResult {
     let ix: usize = (value >> 8).into();
     let nn = (value & 0x0FF) as u8;
 
-    emu.registers[ix] = rand::random::<u8>() & nn;
+    emu.registers[ix] = emu.rng.next_u8() & nn;
     emu.program_counter += 2;
+    Ok(())
 }
 
-/// DXYN
-pub fn draw(emu: &mut Emulator, value: u16) {
-    emu.program_counter += 2;
+/// DXYN, or DXY0 for a SUPER-CHIP 16x16 sprite. With both XO-CHIP planes
+/// selected the sprite for the second plane follows the first in memory.
+pub fn draw(emu: &mut Emulator, value: u16) -> OpResult {
     emu.draw = true;
 
+    let screen_width = emu.screen_width();
+    let screen_height = emu.screen_height();
+
     // value = 0xXYN
     let x = value >> 8;
     let y = (value >> 4) & 0x0F;
-    let x = emu.registers[x as usize] as u16;
-    let y = emu.registers[y as usize] as u16;
-    let w = 8;
-    let h = value & 0x00F;
+
+    // The starting position always wraps around the screen
+    let x = emu.registers[x as usize] as u16 % screen_width;
+    let y = emu.registers[y as usize] as u16 % screen_height;
+    let (w, h) = match value & 0x00F {
+        0 => (16, 16),
+        n => (8, n)
+    };
+    let bytes_per_line = w / 8;
+    let mut address = emu.index_register as usize;
     let mut flipped: bool = false;
 
-    for yline in 0..h {
-        // Each byte is a line
-        let line = emu.memory[(emu.index_register + yline) as usize];
+    let planes = emu.planes;
+
+    for plane in [1, 2].iter().filter(|plane| planes & **plane != 0) {
+        for yline in 0..h {
+            // Each line is one byte, or two for a 16x16 sprite
+            let mut line = (emu.read_memory(address)? as u16) << 8;
+            if bytes_per_line == 2 {
+                line |= emu.read_memory(address + 1)? as u16;
+            }
+            address += bytes_per_line as usize;
 
-        // For every bit in the line
-        for xline in 0..w {
-            // Get the most significant bit and check if it is 1
-            let pixel = match (line & (0x80 >> xline)) != 0 {
-                true => Pixel::ON,
-                false => Pixel::OFF
-            };
+            // For every bit in the line
+            for xline in 0..w {
+                // Only set bits flip pixels
+                if line & (0x8000 >> xline) == 0 {
+                    continue;
+                }
+
+                let (px, py) = (x + xline, y + yline);
+                let on_screen = px < screen_width && py < screen_height;
+
+                // The rest of the sprite is either clipped or wrapped
+                if !on_screen && !emu.quirks.wrap_sprites {
+                    continue;
+                }
 
-            let did_flip = emu.set_pixel(x + xline, y + yline, pixel);
-            flipped = flipped || did_flip;
+                let did_flip = emu.flip_pixel(px % screen_width, py % screen_height, *plane);
+                flipped = flipped || did_flip;
+            }
         }
     }
 
     emu.registers[0xF] = if flipped { 1 } else { 0 };
+    emu.program_counter += 2;
+    Ok(())
 }
 
 /// EX9E & EXA1
-pub fn skip_pressed(emu: &mut Emulator, value: u16) {
+pub fn skip_pressed(emu: &mut Emulator, value: u16) -> OpResult {
     // Get the key from VX
     let x = value >> 8;
     let key = emu.registers[x as usize];
@@ -211,75 +330,105 @@ pub fn skip_pressed(emu: &mut Emulator, value: u16) {
     (match value & 0x0FF {
         0x9E => skip_if_pressed,
         0xA1 => skip_if_not_pressed,
-        _ => panic!("No instruction for 0xE{:X}", value)
-    })(emu, key);
+        _ => return Err(emu.unknown_opcode(0xE000 | value))
+    })(emu, key)
 }
 
-fn skip_if_pressed(emu: &mut Emulator, key: u8) {
-    match emu.keys.get(&key) {
-        Some(KeyState::DOWN) => emu.program_counter += 4,
-        _ => emu.program_counter += 2
-    }
+fn skip_if_pressed(emu: &mut Emulator, key: u8) -> OpResult {
+    let pressed = matches!(emu.keys.get(&key), Some(KeyState::DOWN));
+    emu.skip_if(pressed)
 }
 
-fn skip_if_not_pressed(emu: &mut Emulator, key: u8) {
-    match emu.keys.get(&key) {
-        Some(KeyState::UP) => emu.program_counter += 4,
-        _ => emu.program_counter += 2
-    }
+fn skip_if_not_pressed(emu: &mut Emulator, key: u8) -> OpResult {
+    let released = matches!(emu.keys.get(&key), Some(KeyState::UP));
+    emu.skip_if(released)
 }
 
 /// Misc opcodes starting with F
-pub fn misc_opcodes(emu: &mut Emulator, value: u16) {
+pub fn misc_opcodes(emu: &mut Emulator, value: u16) -> OpResult {
     let x = value >> 8;
     let xi = x as usize;
     let instruction = (value & 0x0FF) as u8;
 
     match instruction {
+        // F000 NNNN, load the following 16 bit word into I
+        0x00 if x == 0 => {
+            let address = emu.program_counter + 2;
+            emu.index_register = (emu.read_memory(address)? as u16) << 8
+                | emu.read_memory(address + 1)? as u16;
+            emu.program_counter += 2;
+        },
+        // FN01, select the XO-CHIP bitplanes to draw to
+        0x01 => emu.planes = (x & 0x3) as u8,
+        // Load the XO-CHIP audio pattern from the 16 bytes at I
+        0x02 if x == 0 => {
+            let mut pattern = [0; 16];
+            for (i, byte) in pattern.iter_mut().enumerate() {
+                *byte = emu.read_memory(emu.index_register as usize + i)?;
+            }
+            emu.audio_pattern = Some(pattern);
+        },
         // Set VX to the value of the delay timer
         0x07 => emu.registers[xi] = emu.delay_timer,
-        // Wait for any keypress and then store it in vx
-        0x0A => {
-            let mut c = emu.number_of_keys_pressed;
-            loop {
-                if emu.number_of_keys_pressed < c {
-                    c = emu.number_of_keys_pressed;
-                }
-
-                else if emu.number_of_keys_pressed > c {
-                    break;
-                }
-            }
-
-            emu.registers[x as usize] = emu.last_key_pressed;
+        // Wait for a key to be pressed and released and then store it in
+        // VX. The instruction runs again every cycle until `set_key` has seen
+        // the key released.
+        0x0A => match emu.key_wait {
+            KeyWait::Released(key) => {
+                emu.registers[xi] = key;
+                emu.key_wait = KeyWait::Idle;
+            },
+            KeyWait::Idle => {
+                emu.key_wait = KeyWait::Waiting;
+                return Ok(());
+            },
+            KeyWait::Waiting | KeyWait::Pressed(_) => return Ok(())
         },
         0x15 => emu.delay_timer = emu.registers[xi],
         0x18 => emu.sound_timer = emu.registers[xi],
-        0x1E => emu.index_register += emu.registers[xi] as u16,
-        0x29 => emu.index_register = FONTSET_LOC + 5 * x,
+        0x1E => emu.index_register = emu.index_register.wrapping_add(emu.registers[xi] as u16),
+        0x29 => emu.index_register = FONTSET_LOC + 5 * (emu.registers[xi] & 0xF) as u16,
+        0x30 => emu.index_register = BIG_FONTSET_LOC + 10 * (emu.registers[xi] & 0xF) as u16,
         0x33 => {
             let bcd = get_binary_coded_decimal(emu.registers[xi]);
-            emu.memory[emu.index_register as usize + 0] = bcd.0;
-            emu.memory[emu.index_register as usize + 1] = bcd.1;
-            emu.memory[emu.index_register as usize + 2] = bcd.2;
+            let i = emu.index_register as usize;
+            emu.write_memory(i, bcd.0)?;
+            emu.write_memory(i + 1, bcd.1)?;
+            emu.write_memory(i + 2, bcd.2)?;
         },
+        0x3A => emu.pitch = emu.registers[xi],
         0x55 => {
-            for i in 0..(x+1) {
-                emu.memory[(emu.index_register + i) as usize]
-                    = emu.registers[i as usize];
+            for i in 0..=xi {
+                emu.write_memory(emu.index_register as usize + i, emu.registers[i])?;
             }
+
+            increment_index(emu, x);
         },
         0x65 => {
-            for i in 0..(x+1) {
-                emu.registers[i as usize]
-                    = emu.memory[(emu.index_register + i) as usize];
+            for i in 0..=xi {
+                emu.registers[i] = emu.read_memory(emu.index_register as usize + i)?;
             }
+
+            increment_index(emu, x);
         }
-        _ => ()
+        // Save and restore registers to the SUPER-CHIP RPL user flags
+        0x75 => emu.rpl_flags[..=xi].copy_from_slice(&emu.registers[..=xi]),
+        0x85 => emu.registers[..=xi].copy_from_slice(&emu.rpl_flags[..=xi]),
+        _ => return Err(emu.unknown_opcode(0xF000 | value))
     };
 
 
     emu.program_counter += 2;
+    Ok(())
+}
+
+/// Moves I past the registers FX55 or FX65 stored or loaded, if the quirks
+/// say it should
+fn increment_index(emu: &mut Emulator, x: u16) {
+    if emu.quirks.load_store_increments_index {
+        let length = if emu.quirks.load_store_increments_by_x { x } else { x + 1 };
+        emu.index_register = emu.index_register.wrapping_add(length);
+    }
 }
 
 fn get_binary_coded_decimal(value: u8) -> (u8, u8, u8) {
@@ -287,30 +436,70 @@ fn get_binary_coded_decimal(value: u8) -> (u8, u8, u8) {
     let y = (value - (x * 100)) / 10;
     let z = value - x * 100 - y * 10;
 
-    return (x, y, z);
+    (x, y, z)
 }
 
 impl Emulator {
-    fn set_pixel(&mut self, x: u16, y: u16, pixel: Pixel) -> bool {
-        let i = (y * Emulator::SCREEN_WIDTH + x) as usize;
+    /// Moves past the next instruction if `condition` holds, which is
+    /// four bytes long if it is an XO-CHIP F000 NNNN
+    fn skip_if(&mut self, condition: bool) -> OpResult {
+        self.program_counter += 2;
+
+        if condition {
+            let next = (self.read_memory(self.program_counter)? as u16) << 8
+                | self.read_memory(self.program_counter + 1)? as u16;
+            self.program_counter += if next == 0xF000 { 4 } else { 2 };
+        }
+
+        Ok(())
+    }
+
+    /// XORs a pixel on `plane`, returning true if that turned it off
+    fn flip_pixel(&mut self, x: u16, y: u16, plane: u8) -> bool {
+        let i = (y * self.screen_width() + x) as usize;
 
-        if i > (Emulator::SCREEN_WIDTH * Emulator::SCREEN_HEIGHT - 1) as usize {
+        if i > (self.screen_width() * self.screen_height() - 1) as usize {
             return false;
         }
 
-        let previous_state: Pixel = self.graphics[i];
+        self.graphics[i] ^= plane;
 
-        if previous_state != pixel {
-            // If the current pixel is different to the new pixel the value
-            // will be set to ON
-            self.graphics[i] = Pixel::ON;
-        } else {
-            // Otherwise it will be OFF
-            self.graphics[i] = Pixel::OFF;
+        // If the pixel changed from ON -> OFF indicate that it was
+        self.graphics[i] & plane == 0
+    }
+
+    /// Blanks the selected planes
+    fn clear_screen(&mut self) {
+        for pixel in self.graphics.iter_mut() {
+            *pixel &= !self.planes;
         }
 
-        // If the pixel changed from ON -> OFF indicate that it was
-        return previous_state == Pixel::ON && self.graphics[i] == Pixel::OFF;
+        self.clear = true;
+    }
+
+    /// Moves the selected planes right `columns` pixels and down `lines`
+    /// pixels, or left and up when negative, blanking what is uncovered
+    fn scroll(&mut self, columns: i32, lines: i32) {
+        let width = self.screen_width() as i32;
+        let height = self.screen_height() as i32;
+        let previous = self.graphics;
+
+        for y in 0..height {
+            for x in 0..width {
+                let (from_x, from_y) = (x - columns, y - lines);
+                let on_screen = from_x >= 0 && from_x < width && from_y >= 0 && from_y < height;
+                let moved = if on_screen {
+                    previous[(from_y * width + from_x) as usize] & self.planes
+                } else {
+                    0
+                };
+
+                let i = (y * width + x) as usize;
+                self.graphics[i] = (self.graphics[i] & !self.planes) | moved;
+            }
+        }
+
+        self.draw = true;
     }
 }
 
@@ -321,11 +510,11 @@ mod tests {
     #[test]
     fn set_index_register() {
         let new_index_reg = 0x210;
-        let mut emu = Emulator::new();
+        let mut emu = Emulator::default();
         emu.memory[emu.program_counter] = 0xA2;
         emu.memory[emu.program_counter + 1] = 0x10;
 
-        emu.emulate_cycle();
+        emu.emulate_cycle().unwrap();
         assert_eq!(emu.index_register, new_index_reg);
     }
 
@@ -335,7 +524,7 @@ mod tests {
     fn subroutine() {
         let subroutine_loc = 0x623;
 
-        let mut emu = Emulator::new();
+        let mut emu = Emulator::default();
         assert_eq!(emu.program_counter, PROGRAM_LOC);
 
         // Jump to subroutine 0x123
@@ -351,19 +540,47 @@ mod tests {
         emu.memory[subroutine_loc + 3] = 0xEE;
 
         for _ in 0..3 {
-            emu.emulate_cycle();
+            emu.emulate_cycle().unwrap();
         }
 
         // Make sure we are on the second instruction
         assert_eq!(PROGRAM_LOC + 2, emu.program_counter);
     }
 
+    #[test]
+    fn stack_underflow() {
+        let mut emu = Emulator::default();
+        let pc = emu.program_counter;
+
+        // Return without ever calling a subroutine
+        emu.memory[pc] = 0x00;
+        emu.memory[pc + 1] = 0xEE;
+
+        assert_eq!(Err(Chip8Error::StackUnderflow { pc }), emu.emulate_cycle());
+    }
+
+    #[test]
+    fn stack_overflow() {
+        let mut emu = Emulator::default();
+        let pc = emu.program_counter;
+
+        // Call this same instruction forever
+        emu.memory[pc] = 0x22;
+        emu.memory[pc + 1] = 0x00;
+
+        for _ in 0..16 {
+            emu.emulate_cycle().unwrap();
+        }
+
+        assert_eq!(Err(Chip8Error::StackOverflow { pc }), emu.emulate_cycle());
+    }
+
 
     /// Test that we can insert a value to a register and then compare against
     /// value to skip the instruction
     #[test]
     fn skip_true() {
-        let mut emu = Emulator::new();
+        let mut emu = Emulator::default();
         let pc = emu.program_counter;
 
         // Set register A to 33
@@ -383,7 +600,7 @@ mod tests {
         emu.memory[pc + 7] = 0x66;
 
         for _ in 0..3 {
-            emu.emulate_cycle();
+            emu.emulate_cycle().unwrap();
         }
 
         // We have moved 3 instructions + one skipped instruction = 4 * 2 = 8
@@ -398,7 +615,7 @@ mod tests {
     /// value to skip the instruction
     #[test]
     fn skip_false() {
-        let mut emu = Emulator::new();
+        let mut emu = Emulator::default();
         let pc = emu.program_counter;
 
         // Set register A to A33
@@ -418,7 +635,7 @@ mod tests {
         emu.memory[pc + 7] = 0x66;
 
         for _ in 0..3 {
-            emu.emulate_cycle();
+            emu.emulate_cycle().unwrap();
         }
 
         // We have moved 3 instructions + one skipped instruction = 4 * 2 = 8
@@ -431,7 +648,7 @@ mod tests {
 
     #[test]
     fn skip_equals() {
-        let mut emu = Emulator::new();
+        let mut emu = Emulator::default();
         let pc = emu.program_counter;        emu.memory[pc] = 0x6A;
         emu.memory[pc + 1] = 0x33;
 
@@ -448,7 +665,7 @@ mod tests {
         emu.memory[pc + 7] = 0x66;
 
         for _ in 0..3 {
-            emu.emulate_cycle();
+            emu.emulate_cycle().unwrap();
         }
 
         // We have moved 3 instructions + one skipped instruction = 4 * 2 = 8
@@ -463,7 +680,7 @@ mod tests {
     /// value to skip the instruction
     #[test]
     fn skip_false2() {
-        let mut emu = Emulator::new();
+        let mut emu = Emulator::default();
         let pc = emu.program_counter;
 
         // Set register A to A33
@@ -483,7 +700,7 @@ mod tests {
         emu.memory[pc + 1] = 0x30;
 
         // Process that instruction
-        emu.emulate_cycle();
+        emu.emulate_cycle().unwrap();
 
         // Make sure we have skipped ahead two instructions
        assert_eq!(emu.program_counter, pc + 4);
@@ -491,7 +708,7 @@ mod tests {
 
     #[test]
     fn not_equals() {
-        let mut emu = Emulator::new();
+        let mut emu = Emulator::default();
         let pc = emu.program_counter;
         emu.registers[2] = 5;
         emu.registers[3] = 6;
@@ -501,7 +718,7 @@ mod tests {
         emu.memory[pc + 1] = 0x30;
 
         // Process that instruction
-        emu.emulate_cycle();
+        emu.emulate_cycle().unwrap();
 
         // Make sure we have skipped ahead two instructions
         assert_eq!(emu.program_counter, pc + 4);
@@ -509,7 +726,7 @@ mod tests {
 
     #[test]
     fn add_to_register() {
-        let mut emu = Emulator::new();
+        let mut emu = Emulator::default();
         let pc = emu.program_counter;
         emu.registers[3] = 5;
 
@@ -517,14 +734,14 @@ mod tests {
         emu.memory[pc] = 0x73;
         emu.memory[pc + 1] = 0x06;
 
-        emu.emulate_cycle();
+        emu.emulate_cycle().unwrap();
 
         assert_eq!(11, emu.registers[3]);
     }
 
     #[test]
     fn test_assign() {
-        let mut emu = Emulator::new();
+        let mut emu = Emulator::default();
         let pc = emu.program_counter;
         let y: usize = 3;
         let x: usize = 0xA;
@@ -536,7 +753,7 @@ mod tests {
         emu.memory[pc + 1] = 0x30;
 
         // Process that instruction
-        emu.emulate_cycle();
+        emu.emulate_cycle().unwrap();
 
         // Make sure we have assigned to the register
         assert_eq!(5, emu.registers[x]);
@@ -563,7 +780,7 @@ mod tests {
 
     #[test]
     fn right_bit_shift() {
-        let mut emu = Emulator::new();
+        let mut emu = Emulator::default();
         let pc = emu.program_counter;
         let x: usize = 0xA;
 
@@ -574,7 +791,7 @@ mod tests {
         emu.memory[pc + 1] = 0x06;
 
         // Process that instruction
-        emu.emulate_cycle();
+        emu.emulate_cycle().unwrap();
 
         // Make sure we have assigned to the register
         assert_eq!(0, emu.registers[x]);
@@ -583,7 +800,7 @@ mod tests {
 
     #[test]
     fn left_bit_shift() {
-        let mut emu = Emulator::new();
+        let mut emu = Emulator::default();
         let pc = emu.program_counter;
         let x: usize = 0xA;
 
@@ -594,7 +811,7 @@ mod tests {
         emu.memory[pc + 1] = 0x0E;
 
         // Process that instruction
-        emu.emulate_cycle();
+        emu.emulate_cycle().unwrap();
 
         // Make sure we have assigned to the register
         assert_eq!(0, emu.registers[x]);
@@ -603,7 +820,7 @@ mod tests {
 
     #[test]
     fn jump_to_address() {
-        let mut emu = Emulator::new();
+        let mut emu = Emulator::default();
         let pc = emu.program_counter;
 
         emu.registers[0] = 5;
@@ -613,7 +830,7 @@ mod tests {
         emu.memory[pc + 1] = 0xBC;
 
         // Process that instruction
-        emu.emulate_cycle();
+        emu.emulate_cycle().unwrap();
 
         // Make sure we have jumped to the address 0xABC + 5
         assert_eq!(0xABC + 5, emu.program_counter);
@@ -621,42 +838,45 @@ mod tests {
 
     #[test]
     fn rand() {
-        let mut emu = Emulator::new();
+        let mut emu = Emulator::default();
         let pc = emu.program_counter;
 
         emu.registers[6] = 0x1F;
+        emu.seed_rng(1);
 
-        // Put a random number into 6
+        // Put a random number into 6, twice
         emu.memory[pc] = 0xC6;
         emu.memory[pc + 1] = 0x0F;
-
-        // Process that instruction
-        emu.emulate_cycle();
-
-        // Make sure that register 6 is random number <= 0x0F
-        assert!(emu.registers[6] <= 0x0F);
+        emu.memory[pc + 2] = 0xC6;
+        emu.memory[pc + 3] = 0xFF;
+
+        // The same seed always gives the same numbers
+        emu.emulate_cycle().unwrap();
+        assert_eq!(0x01, emu.registers[6]);
+        emu.emulate_cycle().unwrap();
+        assert_eq!(0xBE, emu.registers[6]);
     }
 
     #[test]
     fn clear_screen() {
-        let mut emu = Emulator::new();
+        let mut emu = Emulator::default();
         let pc = emu.program_counter;
 
         // Put some random stuff on the screen
-        emu.graphics[14] = Pixel::ON;
-        emu.graphics[02] = Pixel::ON;
-        emu.graphics[4] = Pixel::ON;
+        emu.graphics[14] = 1;
+        emu.graphics[2] = 1;
+        emu.graphics[4] = 1;
 
         // Clear the screen
         emu.memory[pc] = 0x00;
         emu.memory[pc + 1] = 0xE0;
 
         // Process that instruction
-        emu.emulate_cycle();
+        emu.emulate_cycle().unwrap();
 
         // Make sure that the screen is blank
         for pixel in emu.graphics.iter() {
-            assert_eq!(Pixel::OFF, *pixel);
+            assert_eq!(0, *pixel);
         }
     }
 
@@ -667,4 +887,355 @@ mod tests {
         assert_eq!(5, bcd.1);
         assert_eq!(4, bcd.2);
     }
+
+    #[test]
+    fn vf_reset_quirk() {
+        let mut emu = Emulator::new(Quirks { vf_reset: true, ..Quirks::MODERN });
+        let pc = emu.program_counter;
+
+        emu.registers[0xF] = 1;
+
+        // VA |= VB
+        emu.memory[pc] = 0x8A;
+        emu.memory[pc + 1] = 0xB1;
+
+        emu.emulate_cycle().unwrap();
+
+        assert_eq!(0, emu.registers[0xF]);
+    }
+
+    #[test]
+    fn shift_uses_vy_quirk() {
+        let mut emu = Emulator::new(Quirks { shift_uses_vy: true, ..Quirks::MODERN });
+        let pc = emu.program_counter;
+
+        emu.registers[0xA] = 0b00000001;
+        emu.registers[0xB] = 0b00000110;
+
+        // Shift VB to the right and store it in VA
+        emu.memory[pc] = 0x8A;
+        emu.memory[pc + 1] = 0xB6;
+
+        emu.emulate_cycle().unwrap();
+
+        assert_eq!(0b00000011, emu.registers[0xA]);
+        assert_eq!(0, emu.registers[0xF]);
+    }
+
+    #[test]
+    fn load_store_increments_index_quirk() {
+        let mut emu = Emulator::new(Quirks { load_store_increments_index: true, ..Quirks::MODERN });
+        let pc = emu.program_counter;
+
+        emu.index_register = 0x300;
+
+        // Store V0 to V2 in memory
+        emu.memory[pc] = 0xF2;
+        emu.memory[pc + 1] = 0x55;
+
+        emu.emulate_cycle().unwrap();
+
+        assert_eq!(0x303, emu.index_register);
+
+        // CHIP-48 stops one short
+        let mut emu = Emulator::new(Quirks::CHIP_48);
+        let pc = emu.program_counter;
+
+        emu.index_register = 0x300;
+
+        // Load V0 to V2 from memory
+        emu.memory[pc] = 0xF2;
+        emu.memory[pc + 1] = 0x65;
+
+        emu.emulate_cycle().unwrap();
+
+        assert_eq!(0x302, emu.index_register);
+    }
+
+    #[test]
+    fn jump_uses_vx_quirk() {
+        let mut emu = Emulator::new(Quirks { jump_uses_vx: true, ..Quirks::MODERN });
+        let pc = emu.program_counter;
+
+        emu.registers[0] = 1;
+        emu.registers[0xA] = 5;
+
+        // Jump to the address ABC plus VA
+        emu.memory[pc] = 0xBA;
+        emu.memory[pc + 1] = 0xBC;
+
+        emu.emulate_cycle().unwrap();
+
+        assert_eq!(0xABC + 5, emu.program_counter);
+    }
+
+    /// Draw the "0" font sprite two pixels from the right edge of the screen
+    fn draw_on_edge(quirks: Quirks) -> Emulator {
+        let mut emu = Emulator::new(quirks);
+        let pc = emu.program_counter;
+
+        emu.registers[0] = (Emulator::SCREEN_WIDTH - 2) as u8;
+        emu.registers[1] = 0;
+        emu.index_register = FONTSET_LOC;
+
+        emu.memory[pc] = 0xD0;
+        emu.memory[pc + 1] = 0x15;
+
+        emu.emulate_cycle().unwrap();
+        emu
+    }
+
+    #[test]
+    fn sprites_clip() {
+        let emu = draw_on_edge(Quirks::MODERN);
+
+        // The top line of the "0" is 0xF0, two pixels fit on screen
+        assert_eq!(Pixel::ON, emu.get_pixel(Emulator::SCREEN_WIDTH - 1, 0));
+        assert_eq!(Pixel::OFF, emu.get_pixel(0, 0));
+        assert_eq!(Pixel::OFF, emu.get_pixel(0, 1));
+    }
+
+    #[test]
+    fn wrap_sprites_quirk() {
+        let emu = draw_on_edge(Quirks { wrap_sprites: true, ..Quirks::MODERN });
+
+        assert_eq!(Pixel::ON, emu.get_pixel(Emulator::SCREEN_WIDTH - 1, 0));
+        assert_eq!(Pixel::ON, emu.get_pixel(0, 0));
+        assert_eq!(Pixel::ON, emu.get_pixel(1, 0));
+        assert_eq!(Pixel::OFF, emu.get_pixel(2, 0));
+    }
+
+    #[test]
+    fn font_character() {
+        let mut emu = Emulator::default();
+        let pc = emu.program_counter;
+
+        emu.registers[3] = 0xA;
+
+        // Point I at the small and then the big font sprite for VA
+        emu.memory[pc] = 0xF3;
+        emu.memory[pc + 1] = 0x29;
+        emu.memory[pc + 2] = 0xF3;
+        emu.memory[pc + 3] = 0x30;
+
+        emu.emulate_cycle().unwrap();
+        assert_eq!(FONTSET_LOC + 5 * 0xA, emu.index_register);
+
+        emu.emulate_cycle().unwrap();
+        assert_eq!(BIG_FONTSET_LOC + 10 * 0xA, emu.index_register);
+    }
+
+    #[test]
+    fn wait_for_key() {
+        let mut emu = Emulator::default();
+        let pc = emu.program_counter;
+
+        // Wait for a key and put it in V3
+        emu.memory[pc] = 0xF3;
+        emu.memory[pc + 1] = 0x0A;
+        emu.delay_timer = 5;
+
+        // The program stays on the instruction but the timers keep going
+        emu.run_frame(10).unwrap();
+        assert!(emu.is_waiting_for_key());
+        assert_eq!(pc, emu.program_counter);
+        assert_eq!(4, emu.delay_timer());
+
+        // Nothing happens until the key is released
+        emu.set_key(0x7, KeyState::DOWN);
+        emu.emulate_cycle().unwrap();
+        assert_eq!(pc, emu.program_counter);
+
+        emu.set_key(0x7, KeyState::UP);
+        emu.emulate_cycle().unwrap();
+        assert!(!emu.is_waiting_for_key());
+        assert_eq!(0x7, emu.registers[3]);
+        assert_eq!(pc + 2, emu.program_counter);
+    }
+
+    #[test]
+    fn hires_sprite() {
+        let mut emu = Emulator::default();
+        let pc = emu.program_counter;
+
+        emu.registers[0] = 100;
+        emu.registers[1] = 40;
+        emu.index_register = 0x300;
+        for i in 0..32 {
+            emu.memory[0x300 + i] = 0xFF;
+        }
+
+        // Switch to hires and draw a filled 16x16 sprite
+        emu.memory[pc] = 0x00;
+        emu.memory[pc + 1] = 0xFF;
+        emu.memory[pc + 2] = 0xD0;
+        emu.memory[pc + 3] = 0x10;
+
+        emu.emulate_cycle().unwrap();
+        assert_eq!(Emulator::HIRES_SCREEN_WIDTH, emu.screen_width());
+
+        emu.emulate_cycle().unwrap();
+        assert_eq!(Pixel::ON, emu.get_pixel(100, 40));
+        assert_eq!(Pixel::ON, emu.get_pixel(115, 55));
+        assert_eq!(Pixel::OFF, emu.get_pixel(116, 55));
+        assert_eq!(Pixel::OFF, emu.get_pixel(115, 56));
+    }
+
+    #[test]
+    fn scroll() {
+        let mut emu = Emulator::default();
+        let pc = emu.program_counter;
+
+        emu.graphics[0] = 1;
+
+        // Scroll down 3 lines and then right 4 pixels
+        emu.memory[pc] = 0x00;
+        emu.memory[pc + 1] = 0xC3;
+        emu.memory[pc + 2] = 0x00;
+        emu.memory[pc + 3] = 0xFB;
+
+        emu.emulate_cycle().unwrap();
+        assert_eq!(Pixel::OFF, emu.get_pixel(0, 0));
+        assert_eq!(Pixel::ON, emu.get_pixel(0, 3));
+
+        emu.emulate_cycle().unwrap();
+        assert_eq!(Pixel::OFF, emu.get_pixel(0, 3));
+        assert_eq!(Pixel::ON, emu.get_pixel(4, 3));
+    }
+
+    #[test]
+    fn rpl_flags() {
+        let mut emu = Emulator::default();
+        let pc = emu.program_counter;
+
+        emu.registers[0] = 1;
+        emu.registers[1] = 2;
+
+        // Save V0 and V1 to the flags, clobber them and restore them
+        emu.memory[pc] = 0xF1;
+        emu.memory[pc + 1] = 0x75;
+        emu.memory[pc + 2] = 0x60;
+        emu.memory[pc + 3] = 0x00;
+        emu.memory[pc + 4] = 0xF1;
+        emu.memory[pc + 5] = 0x85;
+
+        for _ in 0..3 {
+            emu.emulate_cycle().unwrap();
+        }
+
+        assert_eq!([1, 2], emu.registers[..2]);
+    }
+
+    #[test]
+    fn exit() {
+        let mut emu = Emulator::default();
+        let pc = emu.program_counter;
+
+        emu.memory[pc] = 0x00;
+        emu.memory[pc + 1] = 0xFD;
+
+        emu.emulate_cycle().unwrap();
+        emu.emulate_cycle().unwrap();
+
+        assert!(emu.has_exited());
+        assert_eq!(pc, emu.program_counter);
+    }
+
+    #[test]
+    fn long_index_load() {
+        let mut emu = Emulator::default();
+        let pc = emu.program_counter;
+
+        // Skip the long load as V0 is 0, then load 0xBEEF into I
+        emu.memory[pc] = 0x30;
+        emu.memory[pc + 1] = 0x00;
+        emu.memory[pc + 2] = 0xF0;
+        emu.memory[pc + 3] = 0x00;
+        emu.memory[pc + 4] = 0x12;
+        emu.memory[pc + 5] = 0x34;
+        emu.memory[pc + 6] = 0xF0;
+        emu.memory[pc + 7] = 0x00;
+        emu.memory[pc + 8] = 0xBE;
+        emu.memory[pc + 9] = 0xEF;
+
+        emu.emulate_cycle().unwrap();
+        assert_eq!(pc + 6, emu.program_counter);
+
+        emu.emulate_cycle().unwrap();
+        assert_eq!(0xBEEF, emu.index_register);
+        assert_eq!(pc + 10, emu.program_counter);
+    }
+
+    #[test]
+    fn save_load_register_range() {
+        let mut emu = Emulator::default();
+        let pc = emu.program_counter;
+
+        emu.index_register = 0x300;
+        emu.registers[2] = 1;
+        emu.registers[3] = 2;
+        emu.registers[4] = 3;
+
+        // Save V4 down to V2 and load them back into V5 to V7
+        emu.memory[pc] = 0x54;
+        emu.memory[pc + 1] = 0x22;
+        emu.memory[pc + 2] = 0x55;
+        emu.memory[pc + 3] = 0x73;
+
+        emu.emulate_cycle().unwrap();
+        assert_eq!([3, 2, 1], emu.memory[0x300..0x303]);
+
+        emu.emulate_cycle().unwrap();
+        assert_eq!([3, 2, 1], emu.registers[5..8]);
+        assert_eq!(0x300, emu.index_register);
+    }
+
+    #[test]
+    fn bitplanes() {
+        let mut emu = Emulator::default();
+        let pc = emu.program_counter;
+
+        emu.index_register = 0x300;
+        emu.memory[0x300] = 0b11000000;
+        emu.memory[0x301] = 0b10100000;
+
+        // Select both planes and draw a one line sprite for each
+        emu.memory[pc] = 0xF3;
+        emu.memory[pc + 1] = 0x01;
+        emu.memory[pc + 2] = 0xD0;
+        emu.memory[pc + 3] = 0x01;
+
+        emu.emulate_cycle().unwrap();
+        emu.emulate_cycle().unwrap();
+
+        assert_eq!(3, emu.get_colour(0, 0));
+        assert_eq!(1, emu.get_colour(1, 0));
+        assert_eq!(2, emu.get_colour(2, 0));
+        assert_eq!(Pixel::ON, emu.get_pixel(2, 0));
+        assert_eq!(Pixel::OFF, emu.get_pixel(3, 0));
+    }
+
+    #[test]
+    fn audio_pattern() {
+        let mut emu = Emulator::default();
+        let pc = emu.program_counter;
+
+        emu.index_register = 0x300;
+        emu.memory[0x300] = 0xAA;
+        emu.registers[0] = 112;
+
+        // Load the pattern at I and raise the pitch an octave
+        emu.memory[pc] = 0xF0;
+        emu.memory[pc + 1] = 0x02;
+        emu.memory[pc + 2] = 0xF0;
+        emu.memory[pc + 3] = 0x3A;
+
+        assert_eq!(None, emu.audio_pattern());
+
+        emu.emulate_cycle().unwrap();
+        emu.emulate_cycle().unwrap();
+
+        assert_eq!(0xAA, emu.audio_pattern().unwrap()[0]);
+        assert_eq!(8000.0, emu.audio_playback_rate());
+    }
 }
@@ -0,0 +1,116 @@
+/// Behaviours that differ between the interpreters Chip8 programs were
+/// written for. Each flag picks the less common of two behaviours when set.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Quirks {
+    /// 8XY1, 8XY2 and 8XY3 reset VF to zero
+    pub vf_reset: bool,
+    /// 8XY6 and 8XYE shift VY into VX rather than shifting VX in place
+    pub shift_uses_vy: bool,
+    /// FX55 and FX65 leave I pointing just past the last register stored
+    pub load_store_increments_index: bool,
+    /// BNNN jumps to XNN plus VX rather than NNN plus V0
+    pub jump_uses_vx: bool,
+    /// Sprites drawn over the edge of the screen wrap around to the other side
+    /// rather than being clipped
+    pub wrap_sprites: bool,
+    /// With `load_store_increments_index`, I is left pointing at the last
+    /// register stored rather than just past it, as CHIP-48 did
+    pub load_store_increments_by_x: bool,
+}
+
+impl Quirks {
+    /// The original interpreter on the RCA COSMAC VIP
+    pub const COSMAC_VIP: Quirks = Quirks {
+        vf_reset: true,
+        shift_uses_vy: true,
+        load_store_increments_index: true,
+        jump_uses_vx: false,
+        wrap_sprites: false,
+        load_store_increments_by_x: false,
+    };
+
+    /// CHIP-48 on the HP-48 calculators
+    pub const CHIP_48: Quirks = Quirks {
+        vf_reset: false,
+        shift_uses_vy: false,
+        load_store_increments_index: true,
+        jump_uses_vx: true,
+        wrap_sprites: false,
+        load_store_increments_by_x: true,
+    };
+
+    /// SUPER-CHIP 1.1 on the HP-48 calculators, which stopped FX55 and FX65
+    /// moving I at all
+    pub const SUPER_CHIP: Quirks = Quirks {
+        vf_reset: false,
+        shift_uses_vy: false,
+        load_store_increments_index: false,
+        jump_uses_vx: true,
+        wrap_sprites: false,
+        load_store_increments_by_x: false,
+    };
+
+    /// What most modern interpreters and ROMs written for them expect
+    pub const MODERN: Quirks = Quirks {
+        vf_reset: false,
+        shift_uses_vy: false,
+        load_store_increments_index: false,
+        jump_uses_vx: false,
+        wrap_sprites: false,
+        load_store_increments_by_x: false,
+    };
+
+    /// XO-CHIP as implemented by Octo
+    pub const XO_CHIP: Quirks = Quirks {
+        vf_reset: false,
+        shift_uses_vy: true,
+        load_store_increments_index: true,
+        jump_uses_vx: false,
+        wrap_sprites: true,
+        load_store_increments_by_x: false,
+    };
+
+    /// The names accepted by `Quirks::preset`
+    pub const PRESET_NAMES: [&'static str; 5] = ["vip", "chip48", "schip", "xochip", "modern"];
+
+    /// Looks up a named preset, see `Quirks::PRESET_NAMES`
+    pub fn preset(name: &str) -> Option<Quirks> {
+        match name {
+            "vip" => Some(Quirks::COSMAC_VIP),
+            "chip48" => Some(Quirks::CHIP_48),
+            "schip" => Some(Quirks::SUPER_CHIP),
+            "xochip" => Some(Quirks::XO_CHIP),
+            "modern" => Some(Quirks::MODERN),
+            _ => None
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks::MODERN
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_preset_name_resolves() {
+        for name in Quirks::PRESET_NAMES.iter() {
+            assert!(Quirks::preset(name).is_some(), "Missing preset {}", name);
+        }
+
+        assert_eq!(None, Quirks::preset("octo"));
+    }
+
+    #[test]
+    fn presets_differ() {
+        let presets: Vec<Quirks> = Quirks::PRESET_NAMES.iter().filter_map(|name| Quirks::preset(name)).collect();
+
+        for (i, preset) in presets.iter().enumerate() {
+            assert!(!presets[i + 1..].contains(preset), "{} is the same as a later preset", Quirks::PRESET_NAMES[i]);
+        }
+    }
+}
@@ -0,0 +1,246 @@
+use crate::emulator::*;
+
+// Every save state starts with these followed by the format version
+const MAGIC: &[u8; 4] = b"C8ST";
+const VERSION: u8 = 1;
+
+/// Reads the fields of a save state in order, failing on truncated data
+struct Reader<'a> {
+    data: &'a [u8]
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, length: usize) -> Result<&'a [u8], Chip8Error> {
+        if self.data.len() < length {
+            return Err(Chip8Error::InvalidState("the state is truncated".to_string()));
+        }
+
+        let (bytes, rest) = self.data.split_at(length);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, Chip8Error> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn bool(&mut self) -> Result<bool, Chip8Error> {
+        Ok(self.u8()? != 0)
+    }
+
+    fn u16(&mut self) -> Result<u16, Chip8Error> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, Chip8Error> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.bytes(4)?);
+        Ok(u32::from_be_bytes(bytes))
+    }
+
+    fn u64(&mut self) -> Result<u64, Chip8Error> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.bytes(8)?);
+        Ok(u64::from_be_bytes(bytes))
+    }
+}
+
+impl Emulator {
+    /// Snapshots the complete state of the emulator, to be restored later
+    /// with `load_state`
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = Vec::with_capacity(Emulator::MEMORY_SIZE + Emulator::HIRES_SCREEN_SIZE + 256);
+
+        state.extend(MAGIC);
+        state.push(VERSION);
+
+        let quirks = self.quirks;
+        state.extend(&[
+            quirks.vf_reset as u8,
+            quirks.shift_uses_vy as u8,
+            quirks.load_store_increments_index as u8,
+            quirks.jump_uses_vx as u8,
+            quirks.wrap_sprites as u8,
+            quirks.load_store_increments_by_x as u8
+        ]);
+
+        state.extend(&self.memory[..]);
+        state.extend(&self.registers);
+        state.extend(&self.index_register.to_be_bytes());
+        state.extend(&(self.program_counter as u32).to_be_bytes());
+        state.extend(&self.graphics[..]);
+        state.extend(&[self.planes, self.hires as u8, self.exited as u8]);
+        state.extend(&self.rpl_flags);
+
+        match self.audio_pattern {
+            Some(pattern) => {
+                state.push(1);
+                state.extend(&pattern);
+            },
+            None => state.extend(&[0; 17])
+        }
+
+        state.extend(&[self.pitch, self.delay_timer, self.sound_timer]);
+
+        for address in self.stack.iter() {
+            state.extend(&address.to_be_bytes());
+        }
+        state.push(self.stack_pointer as u8);
+
+        for key in 0x0..0x10 {
+            state.push(match self.get_key(key) {
+                KeyState::DOWN => 1,
+                KeyState::UP => 0
+            });
+        }
+        state.extend(&match self.key_wait {
+            KeyWait::Idle => [0, 0],
+            KeyWait::Waiting => [1, 0],
+            KeyWait::Pressed(key) => [2, key],
+            KeyWait::Released(key) => [3, key]
+        });
+
+        state.extend(&(self.hz_counter.as_nanos() as u64).to_be_bytes());
+        state.extend(&self.rng.state().to_be_bytes());
+
+        state
+    }
+
+    /// Restores a snapshot taken with `save_state`. The emulator is left
+    /// untouched if the state can't be read.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), Chip8Error> {
+        let mut reader = Reader { data: state };
+
+        if reader.bytes(MAGIC.len())? != MAGIC {
+            return Err(Chip8Error::InvalidState("this is not a save state".to_string()));
+        }
+
+        let version = reader.u8()?;
+        if version != VERSION {
+            return Err(Chip8Error::InvalidState(format!("unsupported version {}", version)));
+        }
+
+        let quirks = Quirks {
+            vf_reset: reader.bool()?,
+            shift_uses_vy: reader.bool()?,
+            load_store_increments_index: reader.bool()?,
+            jump_uses_vx: reader.bool()?,
+            wrap_sprites: reader.bool()?,
+            load_store_increments_by_x: reader.bool()?
+        };
+
+        let mut emu = Emulator::new(quirks);
+
+        emu.memory.copy_from_slice(reader.bytes(Emulator::MEMORY_SIZE)?);
+        emu.registers.copy_from_slice(reader.bytes(16)?);
+        emu.index_register = reader.u16()?;
+        emu.program_counter = reader.u32()? as usize;
+        emu.graphics.copy_from_slice(reader.bytes(Emulator::HIRES_SCREEN_SIZE)?);
+        emu.planes = reader.u8()?;
+        if emu.planes > 0x3 {
+            return Err(Chip8Error::InvalidState("only two bitplanes can be selected".to_string()));
+        }
+        emu.hires = reader.bool()?;
+        emu.exited = reader.bool()?;
+        emu.rpl_flags.copy_from_slice(reader.bytes(16)?);
+
+        let has_audio_pattern = reader.bool()?;
+        let mut pattern = [0; 16];
+        pattern.copy_from_slice(reader.bytes(16)?);
+        emu.audio_pattern = if has_audio_pattern { Some(pattern) } else { None };
+
+        emu.pitch = reader.u8()?;
+        emu.delay_timer = reader.u8()?;
+        emu.sound_timer = reader.u8()?;
+
+        for address in emu.stack.iter_mut() {
+            *address = reader.u16()?;
+        }
+        emu.stack_pointer = reader.u8()? as usize;
+        if emu.stack_pointer > emu.stack.len() {
+            return Err(Chip8Error::InvalidState("the stack pointer is out of range".to_string()));
+        }
+
+        for key in 0x0..0x10 {
+            let state = if reader.bool()? { KeyState::DOWN } else { KeyState::UP };
+            emu.keys.insert(key, state);
+        }
+        emu.key_wait = match (reader.u8()?, reader.u8()?) {
+            (0, _) => KeyWait::Idle,
+            (1, _) => KeyWait::Waiting,
+            (2, key) => KeyWait::Pressed(key),
+            (3, key) => KeyWait::Released(key),
+            _ => return Err(Chip8Error::InvalidState("the FX0A state is invalid".to_string()))
+        };
+
+        emu.hz_counter = Duration::from_nanos(reader.u64()?);
+        emu.rng = Rng::new(reader.u64()?);
+
+        // Redraw everything once the state is restored
+        emu.draw = true;
+        emu.clear = true;
+
+        *self = emu;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut emu = Emulator::new(Quirks::COSMAC_VIP);
+        emu.memory[0x300] = 0xAB;
+        emu.registers[3] = 7;
+        emu.index_register = 0x123;
+        emu.program_counter = 0x246;
+        emu.graphics[100] = 3;
+        emu.hires = true;
+        emu.stack[0] = 0x202;
+        emu.stack_pointer = 1;
+        emu.delay_timer = 30;
+        emu.audio_pattern = Some([0xF0; 16]);
+        emu.set_key(0xA, KeyState::DOWN);
+        emu.seed_rng(42);
+
+        let mut restored = Emulator::default();
+        restored.load_state(&emu.save_state()).unwrap();
+
+        assert_eq!(emu.save_state(), restored.save_state());
+        assert_eq!(Quirks::COSMAC_VIP, restored.quirks());
+        assert_eq!(0xAB, restored.memory[0x300]);
+        assert_eq!(0x246, restored.program_counter);
+        assert_eq!(3, restored.get_colour(100, 0));
+        assert_eq!(&[0x202], restored.stack());
+        assert!(matches!(restored.get_key(0xA), KeyState::DOWN));
+        assert_eq!(emu.rng, restored.rng);
+    }
+
+    #[test]
+    fn invalid_states_are_rejected() {
+        let mut emu = Emulator::default();
+        emu.registers[0] = 1;
+
+        let mut state = Emulator::default().save_state();
+        state.truncate(100);
+        assert!(matches!(emu.load_state(&state), Err(Chip8Error::InvalidState(_))));
+        assert!(matches!(emu.load_state(b"not a state"), Err(Chip8Error::InvalidState(_))));
+
+        // Planes past the two XO-CHIP ones
+        let mut state = Emulator::default().save_state();
+        let planes = MAGIC.len() + 1 + 6 + Emulator::MEMORY_SIZE + 16 + 2 + 4 + Emulator::HIRES_SCREEN_SIZE;
+        assert_eq!(1, state[planes]);
+        state[planes] = 4;
+        assert!(matches!(emu.load_state(&state), Err(Chip8Error::InvalidState(_))));
+
+        let mut state = Emulator::default().save_state();
+        state[MAGIC.len()] = VERSION + 1;
+        assert!(matches!(emu.load_state(&state), Err(Chip8Error::InvalidState(_))));
+
+        // Nothing is changed by a failed load
+        assert_eq!(1, emu.registers[0]);
+    }
+}
@@ -0,0 +1,186 @@
+//! Filters that smooth out the flicker of programs that erase and redraw
+//! their sprites with XOR, as the phosphor of an old screen would. They only
+//! change what is shown, the emulator's display is untouched.
+
+use crate::emulator::Emulator;
+use crate::palette::{Colour, Palette};
+
+/// How much of a pixel's glow is left after each frame of decay
+const PERSISTENCE: f32 = 0.6;
+
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub enum Filter {
+    /// Show each frame as it is
+    #[default]
+    Off,
+    /// Show pixels that are on in either of the last two frames
+    Blend,
+    /// Light pixels straight away and fade them out over a few frames
+    Decay
+}
+
+impl Filter {
+    pub const NAMES: [&'static str; 3] = ["off", "blend", "decay"];
+
+    pub fn from_name(name: &str) -> Option<Filter> {
+        match name {
+            "off" => Some(Filter::Off),
+            "blend" => Some(Filter::Blend),
+            "decay" => Some(Filter::Decay),
+            _ => None
+        }
+    }
+}
+
+/// Turns the emulator's display into the colours to show, keeping the recent
+/// frames that the filter needs. `update` should be called once a frame.
+pub struct DisplayFilter {
+    filter: Filter,
+    /// The colour index of each pixel last frame
+    previous: Vec<u8>,
+    /// The colour of each pixel as it fades
+    glow: Vec<[f32; 3]>,
+    colours: Vec<Colour>
+}
+
+impl DisplayFilter {
+    pub fn new(filter: Filter) -> DisplayFilter {
+        DisplayFilter {
+            filter,
+            previous: Vec::new(),
+            glow: Vec::new(),
+            colours: Vec::new()
+        }
+    }
+
+    /// Whether what's shown can change from frame to frame without the
+    /// emulator drawing anything
+    pub fn is_active(&self) -> bool {
+        self.filter != Filter::Off
+    }
+
+    /// The colours from the last `update`
+    pub fn colours(&self) -> &[Colour] {
+        &self.colours
+    }
+
+    /// Filters the next frame of the display, returning the colour of each
+    /// pixel a row at a time
+    pub fn update(&mut self, emu: &Emulator, palette: &Palette) -> &[Colour] {
+        let width = emu.screen_width();
+        let height = emu.screen_height();
+        let size = width as usize * height as usize;
+
+        // Start afresh when switching between lores and hires
+        if self.previous.len() != size {
+            let off = palette.colour(0);
+            self.previous = vec![0; size];
+            self.glow = vec![[off.r as f32, off.g as f32, off.b as f32]; size];
+        }
+
+        self.colours.clear();
+
+        for y in 0..height {
+            for x in 0..width {
+                let i = self.colours.len();
+                let index = emu.get_colour(x, y);
+
+                let colour = match self.filter {
+                    Filter::Off => palette.colour(index),
+                    Filter::Blend => palette.colour(index | self.previous[i]),
+                    Filter::Decay => {
+                        let target = palette.colour(index);
+                        let target = [target.r as f32, target.g as f32, target.b as f32];
+                        let glow = &mut self.glow[i];
+
+                        if index == 0 {
+                            for (channel, target) in glow.iter_mut().zip(target.iter()) {
+                                *channel = target + (*channel - target) * PERSISTENCE;
+                            }
+                        } else {
+                            *glow = target;
+                        }
+
+                        Colour::new(glow[0].round() as u8, glow[1].round() as u8, glow[2].round() as u8)
+                    }
+                };
+
+                self.previous[i] = index;
+                self.colours.push(colour);
+            }
+        }
+
+        &self.colours
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An emulator that draws a pixel in the top left corner, then erases it
+    fn blinking_pixel() -> Emulator {
+        let mut emu = Emulator::default();
+        let program = [0xA3, 0x00, 0xD0, 0x01, 0xD0, 0x01];
+
+        emu.memory[0x200..0x200 + program.len()].copy_from_slice(&program);
+        emu.memory[0x300] = 0x80;
+        emu
+    }
+
+    #[test]
+    fn blend_keeps_erased_pixels_for_a_frame() {
+        let mut emu = blinking_pixel();
+        let palette = Palette::default();
+        let mut filter = DisplayFilter::new(Filter::Blend);
+        let (off, on) = (palette.colour(0), palette.colour(1));
+
+        emu.step().unwrap();
+        emu.step().unwrap();
+        assert_eq!(on, filter.update(&emu, &palette)[0]);
+
+        emu.step().unwrap();
+        assert_eq!(on, filter.update(&emu, &palette)[0]);
+        assert_eq!(off, filter.update(&emu, &palette)[0]);
+        assert_eq!(off, filter.update(&emu, &palette)[1]);
+    }
+
+    #[test]
+    fn decay_fades_out() {
+        let mut emu = blinking_pixel();
+        let palette = Palette::default();
+        let mut filter = DisplayFilter::new(Filter::Decay);
+
+        emu.step().unwrap();
+        emu.step().unwrap();
+        assert_eq!(Colour::new(255, 255, 255), filter.update(&emu, &palette)[0]);
+
+        emu.step().unwrap();
+        assert_eq!(Colour::new(153, 153, 153), filter.update(&emu, &palette)[0]);
+        assert_eq!(Colour::new(92, 92, 92), filter.update(&emu, &palette)[0]);
+
+        for _ in 0..20 {
+            filter.update(&emu, &palette);
+        }
+        assert_eq!(palette.colour(0), filter.update(&emu, &palette)[0]);
+    }
+
+    #[test]
+    fn off_shows_each_frame() {
+        let mut emu = blinking_pixel();
+        let palette = Palette::default();
+        let mut filter = DisplayFilter::new(Filter::Off);
+
+        emu.step().unwrap();
+        emu.step().unwrap();
+        filter.update(&emu, &palette);
+
+        emu.step().unwrap();
+        assert_eq!(palette.colour(0), filter.update(&emu, &palette)[0]);
+        assert_eq!(
+            Emulator::SCREEN_WIDTH as usize * Emulator::SCREEN_HEIGHT as usize,
+            filter.update(&emu, &palette).len()
+        );
+        assert!(!filter.is_active());
+    }
+}
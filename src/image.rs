@@ -0,0 +1,140 @@
+//! Pictures of the display, written as PNG files
+
+use crate::emulator::Emulator;
+use crate::palette::{Colour, Palette};
+
+use std::io::{self, Write};
+
+/// An RGB picture, a row at a time
+#[derive(Clone, PartialEq, Debug)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<Colour>
+}
+
+impl Image {
+    /// Scales up `width` by `height` pixels, each becoming a `scale` by
+    /// `scale` square
+    pub fn from_colours(colours: &[Colour], width: u32, height: u32, scale: u32) -> Image {
+        let scale = scale.max(1);
+        let mut pixels = Vec::with_capacity(colours.len() * (scale * scale) as usize);
+
+        for row in colours.chunks(width as usize).take(height as usize) {
+            for _ in 0..scale {
+                for colour in row {
+                    pixels.extend((0..scale).map(|_| *colour));
+                }
+            }
+        }
+
+        Image { width: width * scale, height: height * scale, pixels }
+    }
+
+    /// The emulator's display in the colours of `palette`, at `scale` times
+    /// its size
+    pub fn capture(emu: &Emulator, palette: &Palette, scale: u32) -> Image {
+        let width = emu.screen_width();
+        let height = emu.screen_height();
+        let colours: Vec<Colour> = (0..height)
+            .flat_map(|y| (0..width).map(move |x| palette.colour(emu.get_colour(x, y))))
+            .collect();
+
+        Image::from_colours(&colours, width.into(), height.into(), scale)
+    }
+
+    /// Stretches the picture to `width` by `height` without smoothing
+    pub fn resized(&self, width: u32, height: u32) -> Image {
+        if (width, height) == (self.width, self.height) {
+            return self.clone();
+        }
+
+        let pixels = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let x = (x as u64 * self.width as u64 / width as u64) as usize;
+                let y = (y as u64 * self.height as u64 / height as u64) as usize;
+                self.pixels[y * self.width as usize + x]
+            })
+            .collect();
+
+        Image { width, height, pixels }
+    }
+
+    /// Writes the picture as an 8 bit RGB PNG
+    pub fn write_png<W: Write>(&self, writer: W) -> io::Result<()> {
+        let mut encoder = png::Encoder::new(writer, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let data: Vec<u8> = self.pixels.iter().flat_map(|colour| vec![colour.r, colour.g, colour.b]).collect();
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&data)?;
+        writer.finish()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scaling() {
+        let (off, on) = (Colour::new(0, 0, 0), Colour::new(255, 255, 255));
+        let image = Image::from_colours(&[on, off, off, on], 2, 2, 2);
+
+        assert_eq!((4, 4), (image.width, image.height));
+        assert_eq!(vec![on, on, off, off, on, on, off, off], image.pixels[..8].to_vec());
+        assert_eq!(vec![off, off, on, on], image.pixels[12..].to_vec());
+
+        let small = image.resized(2, 2);
+        assert_eq!(vec![on, off, off, on], small.pixels);
+        assert_eq!(image, small.resized(4, 4));
+    }
+
+    #[test]
+    fn captures_the_display() {
+        let mut emu = Emulator::default();
+        emu.memory[0x200..0x206].copy_from_slice(&[0xA3, 0x00, 0xD0, 0x01, 0x12, 0x04]);
+        emu.memory[0x300] = 0x80;
+        emu.run_frame(2).unwrap();
+
+        let palette = Palette::preset("amber").unwrap();
+        let image = Image::capture(&emu, &palette, 3);
+        assert_eq!((192, 96), (image.width, image.height));
+        assert_eq!(palette.colour(1), image.pixels[2 * 192 + 2]);
+        assert_eq!(palette.colour(0), image.pixels[2 * 192 + 3]);
+    }
+
+    #[test]
+    fn png_decodes() {
+        // Runs of a colour, gradients and noise
+        let mut state = 1u32;
+        let colours: Vec<Colour> = (0..128 * 64)
+            .map(|i| match (i / 128) % 4 {
+                0 | 1 => Colour::new(10, 20, 30),
+                2 => Colour::new((i % 128) as u8, 0, 255),
+                _ => {
+                    state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                    Colour::new((state >> 8) as u8, (state >> 16) as u8, (state >> 24) as u8)
+                }
+            })
+            .collect();
+        let image = Image::from_colours(&colours, 128, 64, 3);
+
+        let mut png = Vec::new();
+        image.write_png(&mut png).unwrap();
+
+        let mut reader = png::Decoder::new(&png[..]).read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let frame = reader.next_frame(&mut pixels).unwrap();
+
+        assert_eq!((384, 192), (frame.width, frame.height));
+        assert_eq!(png::ColorType::Rgb, frame.color_type);
+        assert_eq!(png::BitDepth::Eight, frame.bit_depth);
+
+        let expected: Vec<u8> = image.pixels.iter().flat_map(|colour| vec![colour.r, colour.g, colour.b]).collect();
+        assert_eq!(expected, pixels[..frame.buffer_size()].to_vec());
+    }
+}
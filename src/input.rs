@@ -0,0 +1,223 @@
+//! Turns the keys and controller buttons pressed in a frontend into presses
+//! of the Chip8 keypad, through the bindings of a `Config`.
+//!
+//! A Chip8 key stays down while anything bound to it is held, so letting go
+//! of the keyboard doesn't release a key that a controller is still holding,
+//! and unplugging a controller releases whatever it was holding.
+//!
+//! Sticks and triggers are treated as a button for each direction, named
+//! after the axis with a `-` or `+`, eg `leftx-` for the left stick pushed
+//! left, which is held while the axis is pushed past `AXIS_THRESHOLD`.
+
+use crate::config::Config;
+use crate::emulator::KeyState;
+use crate::keymap::KeyMap;
+
+/// How far an axis has to be pushed, out of 32767, to hold its button
+pub const AXIS_THRESHOLD: i16 = 16384;
+
+/// An input from a frontend, with just the fields needed to press keys
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum InputEvent<'a> {
+    Key { name: &'a str, state: KeyState },
+    Button { controller: i32, name: &'a str, state: KeyState },
+    /// A stick or trigger moving to `value`, from -32768 to 32767
+    Axis { controller: i32, name: &'a str, value: i16 },
+    Disconnected { controller: i32 }
+}
+
+/// Where an input came from
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Device {
+    Keyboard,
+    /// A game controller by the id the frontend knows it by
+    Controller(i32)
+}
+
+/// The physical inputs held down and the Chip8 keys they press
+pub struct Inputs {
+    keys: KeyMap,
+    buttons: KeyMap,
+    held: Vec<(Device, String, u8)>
+}
+
+impl Inputs {
+    pub fn new(config: &Config) -> Inputs {
+        Inputs {
+            keys: config.keys.clone(),
+            buttons: config.buttons.clone(),
+            held: Vec::new()
+        }
+    }
+
+    /// The keyboard bindings
+    pub fn keys(&self) -> &KeyMap {
+        &self.keys
+    }
+
+    /// The controller bindings
+    pub fn buttons(&self) -> &KeyMap {
+        &self.buttons
+    }
+
+    /// Turns an input into the Chip8 keys that change because of it
+    pub fn handle(&mut self, event: InputEvent) -> Vec<(u8, KeyState)> {
+        match event {
+            InputEvent::Key { name, state } => self.key(name, state).into_iter().collect(),
+            InputEvent::Button { controller, name, state } => {
+                self.button(controller, name, state).into_iter().collect()
+            },
+            InputEvent::Axis { controller, name, value } => {
+                let state = |held: bool| if held { KeyState::DOWN } else { KeyState::UP };
+                let negative = self.button(controller, &format!("{}-", name), state(value <= -AXIS_THRESHOLD));
+                let positive = self.button(controller, &format!("{}+", name), state(value >= AXIS_THRESHOLD));
+
+                negative.into_iter().chain(positive).collect()
+            },
+            InputEvent::Disconnected { controller } => self.disconnect(controller)
+        }
+    }
+
+    /// A key on the keyboard changing state, returning the Chip8 key that
+    /// changes along with it if any
+    pub fn key(&mut self, name: &str, state: KeyState) -> Option<(u8, KeyState)> {
+        self.press(Device::Keyboard, name, state)
+    }
+
+    /// A button on a controller changing state, returning the Chip8 key that
+    /// changes along with it if any
+    pub fn button(&mut self, controller: i32, name: &str, state: KeyState) -> Option<(u8, KeyState)> {
+        self.press(Device::Controller(controller), name, state)
+    }
+
+    /// Forgets the buttons held on a controller that has been unplugged,
+    /// returning the Chip8 keys that are released
+    pub fn disconnect(&mut self, controller: i32) -> Vec<(u8, KeyState)> {
+        let device = Device::Controller(controller);
+        let mut released: Vec<(u8, KeyState)> = Vec::new();
+
+        for (_, _, key) in self.held.iter().filter(|(held, _, _)| *held == device) {
+            if !released.iter().any(|(other, _)| other == key) {
+                released.push((*key, KeyState::UP));
+            }
+        }
+
+        self.held.retain(|(held, _, _)| *held != device);
+        released.retain(|(key, _)| !self.is_held(*key));
+        released
+    }
+
+    fn press(&mut self, device: Device, name: &str, state: KeyState) -> Option<(u8, KeyState)> {
+        let map = match device {
+            Device::Keyboard => &self.keys,
+            Device::Controller(_) => &self.buttons
+        };
+        let key = map.key(name)?;
+        let was_held = self.is_held(key);
+
+        // Compare names as the bindings do, so a key held under one spelling
+        // is released under another
+        self.held.retain(|(held, held_name, _)| *held != device || !held_name.eq_ignore_ascii_case(name));
+        if state == KeyState::DOWN {
+            self.held.push((device, name.to_string(), key));
+        }
+
+        if self.is_held(key) != was_held {
+            Some((key, state))
+        } else {
+            None
+        }
+    }
+
+    fn is_held(&self, key: u8) -> bool {
+        self.held.iter().any(|(_, _, held)| *held == key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn controller_events() {
+        let mut inputs = Inputs::new(&Config::default());
+        let button = |name, state| InputEvent::Button { controller: 2, name, state };
+        let axis = |name, value| InputEvent::Axis { controller: 2, name, value };
+
+        assert_eq!(vec![(0x6, KeyState::DOWN)], inputs.handle(button("a", KeyState::DOWN)));
+        assert_eq!(vec![(0x6, KeyState::UP)], inputs.handle(button("a", KeyState::UP)));
+
+        // The stick presses a direction once it's pushed far enough
+        assert!(inputs.handle(axis("leftx", -1000)).is_empty());
+        assert_eq!(vec![(0x7, KeyState::DOWN)], inputs.handle(axis("leftx", -30000)));
+        assert!(inputs.handle(axis("leftx", -20000)).is_empty());
+        // Flicking it across lets go of one side and presses the other
+        assert_eq!(
+            vec![(0x7, KeyState::UP), (0x9, KeyState::DOWN)],
+            inputs.handle(axis("leftx", 32767))
+        );
+        assert_eq!(vec![(0x9, KeyState::UP)], inputs.handle(axis("leftx", 0)));
+
+        // Axes bound in the config, like triggers, work the same way
+        let config = Config::parse("[buttons]\nA = \"righttrigger+\"", None).unwrap();
+        let mut inputs = Inputs::new(&config);
+        assert_eq!(vec![(0xA, KeyState::DOWN)], inputs.handle(axis("righttrigger", 32767)));
+
+        assert_eq!(vec![(0xA, KeyState::UP)], inputs.handle(InputEvent::Disconnected { controller: 2 }));
+        assert_eq!(
+            vec![(0x5, KeyState::DOWN)],
+            inputs.handle(InputEvent::Key { name: "W", state: KeyState::DOWN })
+        );
+    }
+
+    #[test]
+    fn controller_buttons() {
+        let mut inputs = Inputs::new(&Config::default());
+
+        assert_eq!(Some((0x5, KeyState::DOWN)), inputs.button(0, "dpup", KeyState::DOWN));
+        // Repeats don't press the key again
+        assert_eq!(None, inputs.button(0, "dpup", KeyState::DOWN));
+        assert_eq!(Some((0x5, KeyState::UP)), inputs.button(0, "dpup", KeyState::UP));
+        assert_eq!(None, inputs.button(0, "guide", KeyState::DOWN));
+    }
+
+    #[test]
+    fn keys_stay_down_while_anything_holds_them() {
+        let mut inputs = Inputs::new(&Config::default());
+
+        assert_eq!(Some((0x5, KeyState::DOWN)), inputs.key("W", KeyState::DOWN));
+        assert_eq!(None, inputs.button(0, "dpup", KeyState::DOWN));
+        assert_eq!(None, inputs.button(1, "dpup", KeyState::DOWN));
+        assert_eq!(None, inputs.key("w", KeyState::UP));
+        assert_eq!(None, inputs.button(0, "dpup", KeyState::UP));
+        assert_eq!(Some((0x5, KeyState::UP)), inputs.button(1, "dpup", KeyState::UP));
+    }
+
+    #[test]
+    fn unplugging_releases_held_buttons() {
+        let mut inputs = Inputs::new(&Config::default());
+
+        inputs.button(3, "dpleft", KeyState::DOWN);
+        inputs.button(3, "a", KeyState::DOWN);
+        inputs.button(4, "a", KeyState::DOWN);
+
+        assert_eq!(vec![(0x7, KeyState::UP)], inputs.disconnect(3));
+        assert_eq!(vec![(0x6, KeyState::UP)], inputs.disconnect(4));
+        assert!(inputs.disconnect(4).is_empty());
+    }
+
+    #[test]
+    fn rom_profiles() {
+        let source = r#"
+            [roms."pong.ch8".buttons]
+            1 = "dpup"
+            4 = "dpdown"
+        "#;
+        let mut pong = Inputs::new(&Config::parse(source, Some("pong.ch8")).unwrap());
+        let mut maze = Inputs::new(&Config::parse(source, Some("maze.ch8")).unwrap());
+
+        assert_eq!(Some((0x1, KeyState::DOWN)), pong.button(0, "dpup", KeyState::DOWN));
+        assert_eq!(Some((0x4, KeyState::DOWN)), pong.button(0, "dpdown", KeyState::DOWN));
+        assert_eq!(Some((0x5, KeyState::DOWN)), maze.button(0, "dpup", KeyState::DOWN));
+    }
+}
@@ -0,0 +1,139 @@
+//! Which physical keys press which of the 16 keys of the Chip8 keypad.
+//!
+//! Keys are named the way SDL names them, eg `X`, `1`, `Up` or `Keypad 5`, and
+//! controller buttons the way SDL's controller mappings do, eg `a`, `start` or
+//! `dpup`. Names are compared ignoring case so configs don't have to match
+//! exactly.
+
+/// The keypad in the order it's laid out:
+///
+/// ```text
+/// 1 2 3 C
+/// 4 5 6 D
+/// 7 8 9 E
+/// A 0 B F
+/// ```
+pub const KEYPAD: [u8; 16] = [
+    0x1, 0x2, 0x3, 0xC,
+    0x4, 0x5, 0x6, 0xD,
+    0x7, 0x8, 0x9, 0xE,
+    0xA, 0x0, 0xB, 0xF
+];
+
+/// The left hand side of a QWERTY keyboard, in the same layout as `KEYPAD`
+const DEFAULT_KEYS: [&str; 16] = [
+    "1", "2", "3", "4",
+    "Q", "W", "E", "R",
+    "A", "S", "D", "F",
+    "Z", "X", "C", "V"
+];
+
+/// Controller buttons and the keys they press, the D-pad and left stick
+/// moving with the 5, 7, 8 and 9 keys that most games use for directions
+const DEFAULT_BUTTONS: [(&str, u8); 12] = [
+    ("dpup", 0x5),
+    ("dpleft", 0x7),
+    ("dpdown", 0x8),
+    ("dpright", 0x9),
+    ("lefty-", 0x5),
+    ("leftx-", 0x7),
+    ("lefty+", 0x8),
+    ("leftx+", 0x9),
+    ("a", 0x6),
+    ("b", 0x4),
+    ("x", 0x1),
+    ("y", 0xC)
+];
+
+/// Bindings from the names of physical keys to Chip8 keys, where any number
+/// of physical keys can press the same Chip8 key
+#[derive(Clone, PartialEq, Debug)]
+pub struct KeyMap {
+    bindings: Vec<(String, u8)>
+}
+
+impl KeyMap {
+    /// A map with nothing bound
+    pub fn empty() -> KeyMap {
+        KeyMap { bindings: Vec::new() }
+    }
+
+    /// The default bindings for controller buttons, as `default` is for the
+    /// keyboard
+    pub fn controller() -> KeyMap {
+        KeyMap {
+            bindings: DEFAULT_BUTTONS.iter().map(|(name, key)| (name.to_string(), *key)).collect()
+        }
+    }
+
+    /// The Chip8 key pressed by the physical key called `name`
+    pub fn key(&self, name: &str) -> Option<u8> {
+        self.bindings.iter()
+            .find(|(bound, _)| bound.eq_ignore_ascii_case(name))
+            .map(|(_, key)| *key)
+    }
+
+    /// The names of every physical key that presses `key`
+    pub fn names(&self, key: u8) -> Vec<&str> {
+        self.bindings.iter()
+            .filter(|(_, bound)| *bound == key)
+            .map(|(name, _)| name.as_str())
+            .collect()
+    }
+
+    /// Replaces the physical keys that press `key`. The names are taken from
+    /// any other key they were bound to, so each presses just one.
+    pub fn bind(&mut self, key: u8, names: &[&str]) {
+        self.bindings.retain(|(name, bound)| {
+            *bound != key && !names.iter().any(|new| new.eq_ignore_ascii_case(name))
+        });
+
+        for name in names {
+            self.bindings.push((name.to_string(), key));
+        }
+    }
+}
+
+impl Default for KeyMap {
+    fn default() -> KeyMap {
+        let mut map = KeyMap::empty();
+
+        for (key, name) in KEYPAD.iter().zip(DEFAULT_KEYS.iter()) {
+            map.bind(*key, &[name]);
+        }
+
+        map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_layout() {
+        let map = KeyMap::default();
+
+        assert_eq!(Some(0x0), map.key("X"));
+        assert_eq!(Some(0xC), map.key("4"));
+        assert_eq!(Some(0xF), map.key("v"));
+        assert_eq!(None, map.key("Up"));
+        assert_eq!(vec!["R"], map.names(0xD));
+    }
+
+    #[test]
+    fn binding_several_keys() {
+        let mut map = KeyMap::default();
+        map.bind(0x5, &["Up", "W", "Keypad 8"]);
+        map.bind(0x8, &["Down", "S"]);
+
+        assert_eq!(vec!["Up", "W", "Keypad 8"], map.names(0x5));
+        assert_eq!(Some(0x5), map.key("keypad 8"));
+
+        // Moving W away from 5 leaves its other keys bound
+        map.bind(0x1, &["W"]);
+        assert_eq!(vec!["Up", "Keypad 8"], map.names(0x5));
+        assert_eq!(Some(0x1), map.key("W"));
+        assert!(map.names(0x1).iter().all(|name| *name != "1"));
+    }
+}
@@ -0,0 +1,29 @@
+//! The core of the Chip8 emulator, free of any frontend dependencies so that
+//! it can be embedded in other tools and tested headlessly.
+
+pub mod assembler;
+pub mod audio;
+pub mod config;
+pub mod debugger;
+pub mod disassembler;
+pub mod emulator;
+pub mod filter;
+pub mod image;
+pub mod input;
+pub mod keymap;
+pub mod movie;
+pub mod palette;
+pub mod recording;
+pub mod rewind;
+pub mod terminal;
+
+pub use crate::emulator::{Chip8Error, Emulator, KeyState, Pixel, Quirks};
+pub use crate::assembler::{assemble, AsmError};
+pub use crate::disassembler::disassemble;
+pub use crate::config::Config;
+pub use crate::input::Inputs;
+pub use crate::keymap::KeyMap;
+pub use crate::movie::Movie;
+pub use crate::palette::Palette;
+pub use crate::rewind::Rewind;
+pub use crate::emulator::opcodes;
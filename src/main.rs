@@ -1,96 +1,435 @@
-mod emulator;
 extern crate sdl2;
 
-use crate::emulator::Emulator;
+use chip8::{Config, Emulator, Inputs, KeyState, Movie, Palette, Quirks, Rewind};
+use chip8::audio::{Beeper, Tone, WavWriter, Waveform};
+use chip8::debugger::{Command, Debugger};
+use chip8::filter::{DisplayFilter, Filter};
+use chip8::image::Image;
+use chip8::input::InputEvent;
+use chip8::recording::Recorder;
 
-use sdl2::event::Event;
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use sdl2::controller::GameController;
+use sdl2::event::{Event, WindowEvent};
 use sdl2::pixels;
 use sdl2::keyboard::Keycode;
-use std::time::Duration;
-use sdl2::gfx::primitives::DrawRenderer;
-use sdl2::pixels::Color;
+use std::time::{Duration, Instant};
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::rect::Rect;
+use sdl2::render::{Canvas, Texture};
+use sdl2::video::Window;
+use sdl2::Sdl;
+use std::collections::HashMap;
 use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufWriter, Write};
+use std::path::Path;
 
+const TITLE: &str = "Derek's Chip8 Emulator";
 const SCALE: u16 = 20;
+// Save states are kept in this many numbered slots next to the rom
+const STATE_SLOTS: u8 = 10;
+// The emulator runs a frame of instructions at a time at 60Hz, which is also
+// how often snapshots for rewinding are taken
+const FRAMES_PER_SECOND: usize = 60;
+const FRAME_TIME: Duration = Duration::from_nanos(1_000_000_000 / FRAMES_PER_SECOND as u64);
+const SAMPLE_RATE: i32 = 44_100;
+const SAMPLES_PER_FRAME: usize = SAMPLE_RATE as usize / FRAMES_PER_SECOND;
+// Recordings are this many times the size of the hires display, so they
+// stay the same size when a program switches
+const RECORD_SCALE: u32 = 5;
 const SCREEN_WIDTH: u16 = Emulator::SCREEN_WIDTH * SCALE;
 const SCREEN_HEIGHT: u16 = Emulator::SCREEN_HEIGHT * SCALE;
-static KEY_MAP: &'static [Keycode] = &[
-    Keycode::X,
-    Keycode::Num1,
-    Keycode::Num2,
-    Keycode::Num3,
-    Keycode::Q,
-    Keycode::W,
-    Keycode::E,
-    Keycode::A,
-    Keycode::S,
-    Keycode::D,
-    Keycode::Z,
-    Keycode::C,
-    Keycode::Num4,
-    Keycode::R,
-    Keycode::F,
-    Keycode::V,
-];
-
-fn emu_keypress(emu: &mut Emulator, keycode: Keycode, state: emulator::KeyState) {
-    let key: Option<u8> = match keycode {
-        Keycode::Num1 => Some(0x1),
-        Keycode::Num2 => Some(0x2),
-        Keycode::Num3 => Some(0x3),
-        Keycode::Num4 => Some(0xC),
-        Keycode::Q => Some(0x4),
-        Keycode::W => Some(0x5),
-        Keycode::E => Some(0x6),
-        Keycode::R => Some(0xD),
-        Keycode::A => Some(0x7),
-        Keycode::S => Some(0x8),
-        Keycode::D => Some(0x9),
-        Keycode::F => Some(0xE),
-        Keycode::Z => Some(0xA),
-        Keycode::X => Some(0x0),
-        Keycode::C => Some(0xB),
-        Keycode::V => Some(0xF),
-        _ => None
-    };
 
-    match key {
-        Some(key) => {
-            emu.set_key(key, state);
-        },
-        None => ()
+/// Send the keys that changed to the emulator, recording them before `cycle`
+/// when a movie is being made
+fn emu_keypress<I: IntoIterator<Item = (u8, KeyState)>>(
+    emu: &mut Emulator,
+    mut recording: Option<&mut Movie>,
+    cycle: u64,
+    changes: I
+) {
+    for (key, state) in changes {
+        emu.set_key(key, state);
+
+        if let Some(movie) = &mut recording {
+            movie.record(cycle, key, state);
+        }
+    }
+}
+
+/// Plays the beeper through SDL's audio callback
+struct Speaker(Beeper);
+
+impl AudioCallback for Speaker {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        self.0.fill(out);
+    }
+}
+
+fn open_speaker(sdl_context: &Sdl, tone: Tone) -> Result<AudioDevice<Speaker>, String> {
+    let audio = sdl_context.audio()?;
+    let desired = AudioSpecDesired { freq: Some(SAMPLE_RATE), channels: Some(1), samples: None };
+    let speaker = audio.open_playback(None, &desired, |spec| {
+        Speaker(Beeper::new(tone, spec.freq as u32))
+    })?;
+
+    speaker.resume();
+    Ok(speaker)
+}
+
+/// Options given on the command line
+struct Options {
+    rom: String,
+    quirks: Quirks,
+    debug: bool,
+    /// How many instructions are run each frame
+    cycles_per_frame: usize,
+    /// How many seconds of play can be rewound
+    rewind_seconds: usize,
+    /// Seeds the random numbers for a reproducible run
+    seed: Option<u64>,
+    /// Where to write a movie of the keys pressed
+    record_movie: Option<String>,
+    /// A movie to play back instead of reading the keyboard
+    play: Option<String>,
+    mute: bool,
+    tone: Tone,
+    /// Where to write the sound as a WAV file
+    audio_out: Option<String>,
+    /// Settings to use in place of the usual config file
+    config: Option<String>,
+    palette: Option<Palette>,
+    filter: Option<Filter>,
+    /// How many times bigger than the display screenshots are
+    screenshot_scale: u32,
+    /// Where to record a GIF or PNG frames of the display
+    record: Option<String>
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut rom = None;
+    let mut quirks = Quirks::default();
+    let mut debug = false;
+    let mut cycles_per_frame = 10;
+    let mut rewind_seconds = 30;
+    let mut seed = None;
+    let mut record_movie = None;
+    let mut play = None;
+    let mut mute = false;
+    let mut tone = Tone::default();
+    let mut audio_out = None;
+    let mut config = None;
+    let mut palette = None;
+    let mut filter = None;
+    let mut screenshot_scale = 10;
+    let mut record = None;
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--quirks" => {
+                let name = args.next().ok_or("--quirks needs a preset name")?;
+                quirks = Quirks::preset(name).ok_or(format!(
+                    "Unknown quirks preset {}, expected one of {}",
+                    name,
+                    Quirks::PRESET_NAMES.join(", ")
+                ))?;
+            },
+            "--debug" => debug = true,
+            "--ipf" => {
+                cycles_per_frame = args.next()
+                    .and_then(|ipf| ipf.parse().ok())
+                    .filter(|ipf| *ipf > 0)
+                    .ok_or("--ipf needs a number of instructions per frame")?;
+            },
+            "--rewind" => {
+                rewind_seconds = args.next()
+                    .and_then(|seconds| seconds.parse().ok())
+                    .ok_or("--rewind needs a number of seconds")?;
+            },
+            "--seed" => {
+                seed = Some(args.next()
+                    .and_then(|seed| seed.parse().ok())
+                    .ok_or("--seed needs a number")?);
+            },
+            "--record-movie" => {
+                record_movie = Some(args.next().ok_or("--record-movie needs a path")?.clone());
+            },
+            "--play" => play = Some(args.next().ok_or("--play needs the path to a movie")?.clone()),
+            "--mute" => mute = true,
+            "--audio-out" => audio_out = Some(args.next().ok_or("--audio-out needs a path")?.clone()),
+            "--config" => config = Some(args.next().ok_or("--config needs a path")?.clone()),
+            "--palette" => {
+                palette = Some(Palette::parse(args.next().ok_or("--palette needs a name or colours")?)?);
+            },
+            "--filter" => {
+                let name = args.next().ok_or("--filter needs a name")?;
+                filter = Some(Filter::from_name(name).ok_or(format!(
+                    "Unknown filter {}, expected one of {}",
+                    name,
+                    Filter::NAMES.join(", ")
+                ))?);
+            },
+            "--screenshot-scale" => {
+                screenshot_scale = args.next()
+                    .and_then(|scale| scale.parse().ok())
+                    .filter(|scale| (1..=100).contains(scale))
+                    .ok_or("--screenshot-scale needs a number from 1 to 100")?;
+            },
+            "--record" => record = Some(args.next().ok_or("--record needs a path")?.clone()),
+            "--beep" => {
+                tone.frequency = args.next()
+                    .and_then(|frequency| frequency.parse().ok())
+                    .filter(|frequency| *frequency > 0.0)
+                    .ok_or("--beep needs a frequency in Hz")?;
+            },
+            "--volume" => {
+                let percent: f32 = args.next()
+                    .and_then(|percent| percent.parse().ok())
+                    .filter(|percent| (0.0..=100.0).contains(percent))
+                    .ok_or("--volume needs a percentage")?;
+                tone.volume = percent / 100.0;
+            },
+            "--waveform" => {
+                let name = args.next().ok_or("--waveform needs a name")?;
+                tone.waveform = Waveform::from_name(name).ok_or(format!(
+                    "Unknown waveform {}, expected one of {}",
+                    name,
+                    Waveform::NAMES.join(", ")
+                ))?;
+            },
+            _ if arg.starts_with("--") => return Err(format!("Unknown option {}", arg)),
+            _ if rom.is_some() => return Err(format!("Only one rom can be given, not {} as well", arg)),
+            _ => rom = Some(arg.clone())
+        }
     }
+
+    Ok(Options {
+        rom: rom.ok_or("Please supply a path to the chip8 rom you wish to emulate")?,
+        quirks,
+        debug,
+        cycles_per_frame,
+        rewind_seconds,
+        seed,
+        record_movie,
+        play,
+        mute,
+        tone,
+        audio_out,
+        config,
+        palette,
+        filter,
+        screenshot_scale,
+        record
+    })
+}
+
+/// The window and what's needed to draw the emulator's display in it
+struct Screen<'r> {
+    canvas: Canvas<Window>,
+    /// Big enough for hires mode, lores displays use the top left corner
+    texture: Texture<'r>,
+    palette: Palette,
+    filter: DisplayFilter,
+    /// The size of the display last drawn
+    size: (u32, u32)
 }
 
-/// Write emulator info to the terminal
-fn write_emu_info(emu: &mut Emulator) {
-    // Clear any existing stuff
-    print!("{esc}[2J{esc}[1;1H", esc = 27 as char);
+impl<'r> Screen<'r> {
+    /// Draw the emulator's display to the whole window. The filtered pixels
+    /// are copied into the texture, which the GPU then stretches over the
+    /// window.
+    fn draw(&mut self, emu: &Emulator) -> Result<(), String> {
+        let width = emu.screen_width() as usize;
+        let area = Rect::new(0, 0, width as u32, emu.screen_height().into());
+        self.size = (area.width(), area.height());
+        let colours = self.filter.update(emu, &self.palette);
 
-    // Write the state of each input
-    println!("Input\n-----\n");
-    for (value, key) in KEY_MAP.iter().enumerate() {
-        println!("Key: [{}]\tValue: {:X}\tState: {:?}", key, value, emu.get_key(value as u8));
+        self.texture.with_lock(area, |buffer, pitch| {
+            for (i, colour) in colours.iter().enumerate() {
+                let at = (i / width) * pitch + (i % width) * 3;
+                buffer[at..at + 3].copy_from_slice(&[colour.r, colour.g, colour.b]);
+            }
+        })?;
+
+        self.canvas.copy(&self.texture, area, None)?;
+        self.canvas.present();
+        Ok(())
+    }
+
+    /// What was last drawn, with each hires pixel `scale` pixels wide
+    fn capture(&self, scale: u32) -> Image {
+        let (width, height) = self.size;
+        let scale = scale * Emulator::HIRES_SCREEN_WIDTH as u32 / width.max(1);
+
+        Image::from_colours(self.filter.colours(), width, height, scale)
+    }
+
+    /// Show a message in the title bar, as the terminal is cleared every frame
+    fn set_status(&mut self, message: &str) -> Result<(), String> {
+        self.canvas.window_mut()
+            .set_title(&format!("{} - {}", TITLE, message))
+            .map_err(|e| e.to_string())
     }
 }
 
+/// Prompt for debugger commands until one resumes the emulator, returning
+/// false if the user wants to quit
+fn debug_prompt(
+    debugger: &mut Debugger,
+    emu: &mut Emulator,
+    screen: &mut Screen
+) -> Result<bool, String> {
+    let stdin = io::stdin();
+
+    loop {
+        print!("(chip8) ");
+        io::stdout().flush().map_err(|e| e.to_string())?;
+
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).map_err(|e| e.to_string())? == 0 {
+            return Ok(false);
+        }
+
+        let command = match Command::parse(&line) {
+            Ok(command) => command,
+            Err(error) => {
+                println!("{}", error);
+                continue;
+            }
+        };
+
+        match command {
+            Command::Quit => return Ok(false),
+            Command::Continue => return Ok(true),
+            _ => match debugger.execute(emu, &command) {
+                Ok(output) => println!("{}", output),
+                Err(error) => println!("{}", error)
+            }
+        }
+
+        screen.draw(emu)?;
+    }
+}
+
+/// Where the save state for `slot` of a rom is kept
+fn state_path(rom: &str, slot: u8) -> String {
+    format!("{}.state{}", rom, slot)
+}
+
+fn save_state(emu: &Emulator, rom: &str, slot: u8) -> Result<String, String> {
+    let path = state_path(rom, slot);
+    fs::write(&path, emu.save_state()).map_err(|e| format!("Could not write {}: {}", path, e))?;
+
+    Ok(format!("Saved slot {}", slot))
+}
+
+fn load_state(emu: &mut Emulator, rom: &str, slot: u8) -> Result<String, String> {
+    let path = state_path(rom, slot);
+    let state = fs::read(&path).map_err(|_| format!("Slot {} is empty", slot))?;
+    emu.load_state(&state).map_err(|e| e.to_string())?;
+
+    Ok(format!("Loaded slot {}", slot))
+}
+
+/// The first of `ROM-0.EXTENSION`, `ROM-1.EXTENSION`... that doesn't exist
+fn free_path(rom: &str, extension: &str) -> String {
+    (0..)
+        .map(|number| format!("{}-{}.{}", rom, number, extension))
+        .find(|path| !Path::new(path).exists())
+        .unwrap_or_else(|| format!("{}.{}", rom, extension))
+}
+
+/// Write the display to the next free `ROM-N.png`
+fn screenshot(emu: &Emulator, rom: &str, palette: &Palette, scale: u32) -> Result<String, String> {
+    let path = free_path(rom, "png");
+    let file = File::create(&path).map_err(|e| format!("Could not create {}: {}", path, e))?;
+
+    Image::capture(emu, palette, scale)
+        .write_png(BufWriter::new(file))
+        .map_err(|e| format!("Could not write {}: {}", path, e))?;
+
+    Ok(format!("Saved {}", path))
+}
+
+/// `chip8 disasm ROM`, print a listing of the rom
+fn disasm(args: &[String]) -> Result<(), String> {
+    let path = args.first().ok_or("Please supply a path to the chip8 rom you wish to disassemble")?;
+    let rom = fs::read(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+
+    for line in chip8::disassemble(&rom, Emulator::PROGRAM_START as u16) {
+        println!("{}", line);
+    }
+
+    Ok(())
+}
+
+/// `chip8 asm SOURCE ROM`, assemble a program into a rom
+fn asm(args: &[String]) -> Result<(), String> {
+    let (source_path, rom_path) = match args {
+        [source, rom] => (source, rom),
+        _ => return Err("Please supply the source to assemble and the rom to write".to_string())
+    };
+
+    let source = fs::read_to_string(source_path)
+        .map_err(|e| format!("Could not read {}: {}", source_path, e))?;
+    let rom = chip8::assemble(&source).map_err(|e| format!("{}: {}", source_path, e))?;
+
+    fs::write(rom_path, rom).map_err(|e| format!("Could not write {}: {}", rom_path, e))
+}
+
 fn main() -> Result<(), String> {
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
-        return Err("Please supply a path to the chip8 rom you wish to emulate".to_string());
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    match args.first().map(String::as_str) {
+        Some("disasm") => return disasm(&args[1..]),
+        Some("asm") => return asm(&args[1..]),
+        _ => ()
     }
 
-    let path = &args[1];
+    let options = parse_args(&args)?;
+    let config = Config::find(options.config.as_deref(), &options.rom)?;
 
-    //let mut emu = load_emu();
-    let mut emu = Emulator::load(&path);
+    if options.record_movie.is_some() && options.play.is_some() {
+        return Err("Only one of --record-movie and --play can be given".to_string());
+    }
 
-    let white: Color = Color::RGB(255, 255, 255);
-    let black: Color = Color::RGB(0, 0, 0);
+    let movie = match &options.play {
+        Some(path) => {
+            let source = fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+            Some(Movie::parse(&source).map_err(|e| format!("{}: {}", path, e))?)
+        },
+        None => None
+    };
+
+    // Movies replay with the quirks and seed they were recorded with
+    let quirks = movie.as_ref().map_or(options.quirks, |movie| movie.quirks);
+    let mut emu = Emulator::load(&options.rom, quirks).map_err(|e| e.to_string())?;
+    let seed = match &movie {
+        Some(movie) => Some(movie.seed),
+        None if options.record_movie.is_some() => Some(options.seed.unwrap_or_else(rand::random)),
+        None => options.seed
+    };
+    if let Some(seed) = seed {
+        emu.seed_rng(seed);
+    }
+
+    // Movies replay at the speed they were recorded at too, as the timers tick
+    // between frames
+    let cycles_per_frame = movie.as_ref()
+        .map_or(options.cycles_per_frame, |movie| movie.cycles_per_frame);
+    let mut playback = movie.as_ref().map(Movie::play);
+    let mut recording = options.record_movie.as_ref()
+        .map(|_| Movie::new(seed.unwrap_or_default(), quirks, cycles_per_frame));
+    // Going back in time would throw a movie out of sync
+    let movie_active = movie.is_some() || recording.is_some();
 
     let sdl_context = sdl2::init()?;
     let video_subsys = sdl_context.video()?;
-    let window = video_subsys.window("Derek's Chip8 Emulator", SCREEN_WIDTH.into(), SCREEN_HEIGHT.into())
+    let window = video_subsys.window(TITLE, SCREEN_WIDTH.into(), SCREEN_HEIGHT.into())
         .position_centered()
         .opengl()
         .build()
@@ -102,57 +441,324 @@ fn main() -> Result<(), String> {
     canvas.clear();
     canvas.present();
 
+    let texture_creator = canvas.texture_creator();
+    let texture = texture_creator.create_texture_streaming(
+        PixelFormatEnum::RGB24,
+        Emulator::HIRES_SCREEN_WIDTH.into(),
+        Emulator::HIRES_SCREEN_HEIGHT.into()
+    ).map_err(|e| e.to_string())?;
+
+    // F8 cycles through the presets, starting from the palette chosen
+    let palette = options.palette.clone().unwrap_or_else(|| config.palette.clone());
+    let mut palettes = Palette::presets();
+    let mut palette_index = match palettes.iter().position(|preset| *preset == palette) {
+        Some(index) => index,
+        None => {
+            palettes.insert(0, palette.clone());
+            0
+        }
+    };
+    let filter = DisplayFilter::new(options.filter.unwrap_or(config.filter));
+    let mut screen = Screen { canvas, texture, palette, filter, size: (0, 0) };
+
+    // Recording starts straight away when asked for. F11 pauses and resumes
+    // it, or starts recording to the next free `ROM-N.gif`.
+    let record_size = (
+        Emulator::HIRES_SCREEN_WIDTH as u32 * RECORD_SCALE,
+        Emulator::HIRES_SCREEN_HEIGHT as u32 * RECORD_SCALE
+    );
+    let mut recorder = match &options.record {
+        Some(path) => Some(Recorder::create(path, record_size.0, record_size.1)
+            .map_err(|e| format!("Could not record to {}: {}", path, e))?),
+        None => None
+    };
+    let mut capturing = recorder.is_some();
+
     let mut events = sdl_context.event_pump()?;
 
-    'main: loop {
-        emu.emulate_cycle();
+    // Controllers are opened as SDL reports them being plugged in, which it
+    // does at startup for those already connected
+    let controller_subsys = sdl_context.game_controller()?;
+    let mut controllers: HashMap<i32, GameController> = HashMap::new();
+    let mut inputs = Inputs::new(&config);
+
+    // Carry on without sound rather than not at all if there's no audio device
+    let mut speaker = if options.mute {
+        None
+    } else {
+        open_speaker(&sdl_context, options.tone)
+            .map_err(|e| eprintln!("Could not open the audio device, running muted: {}", e))
+            .ok()
+    };
+
+    // Written a frame at a time, in step with the emulator rather than the
+    // speaker
+    let mut audio_out = match &options.audio_out {
+        Some(path) => {
+            let file = File::create(path).map_err(|e| format!("Could not create {}: {}", path, e))?;
+            let wav = WavWriter::new(BufWriter::new(file), SAMPLE_RATE as u32).map_err(|e| e.to_string())?;
+            Some((Beeper::new(options.tone, SAMPLE_RATE as u32), wav))
+        },
+        None => None
+    };
+    let mut samples = [0.0; SAMPLES_PER_FRAME];
+    let mut debugger = Debugger::new(cycles_per_frame);
+    let mut slot = 0;
+    let mut rewind = Rewind::new(options.rewind_seconds * FRAMES_PER_SECOND);
+    let mut rewinding = false;
+    let mut cycle: u64 = 0;
+    let mut redraw = true;
+    let mut next_frame = Instant::now();
+    let mut error = None;
+    // Set when the debugger resumes so that a breakpoint on the instruction
+    // under the cursor doesn't stop the emulator straight away
+    let mut resumed = false;
 
-        canvas.clear();
+    // The debugger starts paused before the first instruction
+    if options.debug {
+        println!("Type help for a list of debugger commands, press F10 in the window to pause");
+        if !debug_prompt(&mut debugger, &mut emu, &mut screen)? {
+            return Ok(());
+        }
+        resumed = true;
+    }
 
+    'main: loop {
         for event in events.poll_iter() {
             match event {
                 Event::Quit {..} |
                 Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
                     break 'main
                 },
-                // Send the rest of the keypresses to the emulator
-                Event::KeyDown { keycode: Some(keycode), .. } => emu_keypress(
-                    &mut emu,
-                    keycode,
-                    emulator::KeyState::DOWN
-                ),
-                Event::KeyUp { keycode: Some(keycode), .. } => emu_keypress(
-                    &mut emu,
-                    keycode,
-                    emulator::KeyState::UP
-                ),
+                Event::KeyDown { keycode: Some(Keycode::F10), .. } if options.debug => {
+                    println!("{}", chip8::debugger::disassemble_around(&emu, 1));
+                    if !debug_prompt(&mut debugger, &mut emu, &mut screen)? {
+                        break 'main;
+                    }
+                    resumed = true;
+                    next_frame = Instant::now();
+                },
+                Event::KeyDown { keycode: Some(Keycode::F5), .. } => {
+                    let status = save_state(&emu, &options.rom, slot).unwrap_or_else(|e| e);
+                    screen.set_status(&status)?;
+                },
+                Event::KeyDown { keycode: Some(Keycode::F8), .. } => {
+                    palette_index = (palette_index + 1) % palettes.len();
+                    screen.palette = palettes[palette_index].clone();
+                    screen.set_status(&format!("Palette {}", screen.palette.name))?;
+                    redraw = true;
+                },
+                Event::KeyDown { keycode: Some(Keycode::F12), .. } => {
+                    let status = screenshot(&emu, &options.rom, &screen.palette, options.screenshot_scale)
+                        .unwrap_or_else(|e| e);
+                    screen.set_status(&status)?;
+                },
+                Event::KeyDown { keycode: Some(Keycode::F11), .. } => {
+                    if recorder.is_none() {
+                        let path = free_path(&options.rom, "gif");
+                        match Recorder::create(&path, record_size.0, record_size.1) {
+                            Ok(created) => recorder = Some(created),
+                            Err(e) => {
+                                screen.set_status(&format!("Could not record to {}: {}", path, e))?;
+                                continue;
+                            }
+                        }
+                    }
+
+                    capturing = !capturing;
+                    screen.set_status(if capturing { "Recording" } else { "Recording paused" })?;
+                },
+                Event::KeyDown { keycode: Some(Keycode::F9), .. } if !movie_active => {
+                    let status = load_state(&mut emu, &options.rom, slot).unwrap_or_else(|e| e);
+                    screen.set_status(&status)?;
+                    redraw = true;
+                },
+                Event::KeyDown { keycode: Some(Keycode::F6), .. } => {
+                    slot = (slot + STATE_SLOTS - 1) % STATE_SLOTS;
+                    screen.set_status(&format!("Slot {}", slot))?;
+                },
+                Event::KeyDown { keycode: Some(Keycode::F7), .. } => {
+                    slot = (slot + 1) % STATE_SLOTS;
+                    screen.set_status(&format!("Slot {}", slot))?;
+                },
+                // The window's contents are lost when it's uncovered or resized
+                Event::Window { win_event: WindowEvent::Exposed, .. } |
+                Event::Window { win_event: WindowEvent::SizeChanged(..), .. } => redraw = true,
+                Event::KeyDown { keycode: Some(Keycode::Backspace), .. } if !movie_active => rewinding = true,
+                Event::KeyUp { keycode: Some(Keycode::Backspace), .. } => rewinding = false,
+                Event::ControllerDeviceAdded { which, .. } => {
+                    match controller_subsys.open(which) {
+                        Ok(controller) => {
+                            screen.set_status(&format!("Connected {}", controller.name()))?;
+                            controllers.insert(controller.instance_id(), controller);
+                        },
+                        Err(e) => screen.set_status(&format!("Could not open controller: {}", e))?
+                    }
+                },
+                Event::ControllerDeviceRemoved { which, .. } => {
+                    if let Some(controller) = controllers.remove(&which) {
+                        screen.set_status(&format!("Disconnected {}", controller.name()))?;
+                    }
+
+                    let released = inputs.handle(InputEvent::Disconnected { controller: which });
+                    if playback.is_none() {
+                        emu_keypress(&mut emu, recording.as_mut(), cycle, released);
+                    }
+                },
+                // Send the rest of the keypresses to the emulator, unless a
+                // movie is playing them
+                Event::KeyDown { keycode: Some(keycode), .. } if playback.is_none() => {
+                    let event = InputEvent::Key { name: &keycode.name(), state: KeyState::DOWN };
+                    emu_keypress(&mut emu, recording.as_mut(), cycle, inputs.handle(event));
+                },
+                Event::KeyUp { keycode: Some(keycode), .. } if playback.is_none() => {
+                    let event = InputEvent::Key { name: &keycode.name(), state: KeyState::UP };
+                    emu_keypress(&mut emu, recording.as_mut(), cycle, inputs.handle(event));
+                },
+                Event::ControllerButtonDown { which, button, .. } if playback.is_none() => {
+                    let name = button.string();
+                    let event = InputEvent::Button { controller: which, name: &name, state: KeyState::DOWN };
+                    emu_keypress(&mut emu, recording.as_mut(), cycle, inputs.handle(event));
+                },
+                Event::ControllerButtonUp { which, button, .. } if playback.is_none() => {
+                    let name = button.string();
+                    let event = InputEvent::Button { controller: which, name: &name, state: KeyState::UP };
+                    emu_keypress(&mut emu, recording.as_mut(), cycle, inputs.handle(event));
+                },
+                Event::ControllerAxisMotion { which, axis, value, .. } if playback.is_none() => {
+                    let event = InputEvent::Axis { controller: which, name: &axis.string(), value };
+                    emu_keypress(&mut emu, recording.as_mut(), cycle, inputs.handle(event));
+                },
                 _ => {}
             }
         }
 
-        for y in 0..Emulator::SCREEN_HEIGHT {
-            for x in 0..Emulator::SCREEN_WIDTH {
-                let pixel = match emu.get_pixel(x, y) {
-                    emulator::Pixel::ON => white,
-                    emulator::Pixel::OFF => black
-                };
-
-                canvas.box_(
-                    (x * SCALE) as i16,
-                    (y * SCALE) as i16,
-                    (x * SCALE + SCALE) as i16,
-                    (y * SCALE + SCALE) as i16,
-                    pixel
-                ).unwrap();
+        // Play the recorded frames backwards while the rewind key is held
+        if rewinding {
+            rewind.step_back(&mut emu).map_err(|e| e.to_string())?;
+            redraw = true;
+        } else {
+            if let Some(inputs) = &mut playback {
+                inputs.apply(&mut emu, cycle);
+
+                if inputs.is_finished() {
+                    playback = None;
+                    screen.set_status("The movie has finished")?;
+                }
+            }
+
+            if options.debug {
+                // Run one instruction at a time to stop at breakpoints, until
+                // the debugger has run a frame's worth including any stepped
+                // through at the prompt
+                loop {
+                    if resumed {
+                        resumed = false;
+                    } else if let Some(index) = debugger.hit(&emu) {
+                        println!("Hit breakpoint {}, {}", index, debugger.breakpoints()[index]);
+                        println!("{}", chip8::debugger::disassemble_around(&emu, 1));
+
+                        if !debug_prompt(&mut debugger, &mut emu, &mut screen)? {
+                            break 'main;
+                        }
+                        next_frame = Instant::now();
+                    }
+
+                    let finished = match debugger.run_instruction(&mut emu) {
+                        Ok(()) => {
+                            redraw |= emu.draw || emu.clear;
+                            debugger.is_frame_finished()
+                        },
+                        // Let the program be inspected rather than quitting
+                        Err(error) => {
+                            println!("{}", error);
+                            if !debug_prompt(&mut debugger, &mut emu, &mut screen)? {
+                                break 'main;
+                            }
+                            resumed = true;
+                            next_frame = Instant::now();
+                            false
+                        }
+                    };
+                    cycle = debugger.cycle();
+
+                    if finished || emu.has_exited() {
+                        break;
+                    }
+                }
+            } else {
+                if let Err(e) = emu.run_frame(cycles_per_frame) {
+                    error = Some(e.to_string());
+                    break 'main;
+                }
+
+                redraw |= emu.draw || emu.clear;
+                cycle += cycles_per_frame as u64;
+            }
+
+            // The program asked to stop with the SUPER-CHIP exit instruction
+            if emu.has_exited() {
+                break 'main;
+            }
+
+            if options.rewind_seconds > 0 && !movie_active {
+                rewind.push(&emu);
             }
         }
 
-        // Write debugging info to the terminal
-        write_emu_info(&mut emu);
+        if let Some(speaker) = &mut speaker {
+            let mut beeper = speaker.lock();
+            beeper.0.update(&emu);
+            beeper.0.set_active(emu.is_sound_active() && !rewinding);
+        }
 
-        canvas.present();
-        ::std::thread::sleep(Duration::new(0, 1_000_000_000u32 / 240));
+        // The export is silent while rewinding, as the speaker is
+        if let Some((beeper, wav)) = &mut audio_out {
+            if rewinding {
+                samples = [0.0; SAMPLES_PER_FRAME];
+            } else {
+                beeper.update(&emu);
+                beeper.fill(&mut samples);
+            }
+            wav.write(&samples).map_err(|e| e.to_string())?;
+        }
+
+        // Only redraw the window when the display has changed, or could have
+        // through the filter, or the window needs drawing again
+        if redraw || screen.filter.is_active() {
+            screen.draw(&emu)?;
+            redraw = false;
+        }
+
+        // Every frame is recorded, whether or not it was redrawn
+        if let (Some(recorder), true) = (&mut recorder, capturing) {
+            recorder.write(&screen.capture(RECORD_SCALE)).map_err(|e| e.to_string())?;
+        }
+
+        // Wait for the next frame, starting again from now if running the
+        // last one took longer than a frame
+        next_frame += FRAME_TIME;
+        match next_frame.checked_duration_since(Instant::now()) {
+            Some(wait) => ::std::thread::sleep(wait),
+            None => next_frame = Instant::now()
+        }
     }
 
-    Ok(())
+    // Keep the movie even when the program crashed, that's when it's wanted
+    if let (Some(path), Some(movie)) = (&options.record_movie, &recording) {
+        fs::write(path, movie.to_string()).map_err(|e| format!("Could not write {}: {}", path, e))?;
+    }
+
+    if let Some((_, wav)) = audio_out {
+        wav.finish().map_err(|e| e.to_string())?;
+    }
+
+    if let Some(recorder) = recorder {
+        recorder.finish().map_err(|e| e.to_string())?;
+    }
+
+    match error {
+        Some(error) => Err(error),
+        None => Ok(())
+    }
 }
@@ -0,0 +1,256 @@
+//! Recordings of the keys pressed during a run, which replay identically
+//! given the same rom since the random numbers are seeded too.
+//!
+//! Movies are text, a header followed by one `<cycle> <key> <down|up>` per
+//! line with the key in hex:
+//!
+//! ```text
+//! chip8-movie 1
+//! seed 1234
+//! quirks 000000
+//! ipf 10
+//! 120 5 down
+//! 180 5 up
+//! ```
+//!
+//! The quirks are the flags of `Quirks` as 1s and 0s, in the order they are
+//! declared. `ipf` is the number of instructions run each frame, as the
+//! timers tick between frames.
+
+use crate::emulator::{Emulator, KeyState, Quirks};
+
+use std::fmt;
+use std::iter::Peekable;
+use std::slice::Iter;
+
+const HEADER: &str = "chip8-movie 1";
+
+/// A key changing state before the cycle with the given number is run
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct MovieInput {
+    pub cycle: u64,
+    pub key: u8,
+    pub state: KeyState
+}
+
+/// Everything needed to replay a run apart from the rom
+#[derive(Clone, PartialEq, Debug)]
+pub struct Movie {
+    pub seed: u64,
+    pub quirks: Quirks,
+    pub cycles_per_frame: usize,
+    pub inputs: Vec<MovieInput>
+}
+
+impl Movie {
+    pub fn new(seed: u64, quirks: Quirks, cycles_per_frame: usize) -> Movie {
+        Movie { seed, quirks, cycles_per_frame, inputs: Vec::new() }
+    }
+
+    /// Creates an emulator for the rom set up the way this movie was recorded
+    pub fn emulator(&self, rom: &[u8]) -> Result<Emulator, crate::Chip8Error> {
+        let mut emu = Emulator::load_bytes(rom, self.quirks)?;
+        emu.seed_rng(self.seed);
+        Ok(emu)
+    }
+
+    /// Records a key changing state before `cycle` is run
+    pub fn record(&mut self, cycle: u64, key: u8, state: KeyState) {
+        self.inputs.push(MovieInput { cycle, key, state });
+    }
+
+    /// Plays the inputs back from the start
+    pub fn play(&self) -> Playback<'_> {
+        Playback { inputs: self.inputs.iter().peekable() }
+    }
+
+    pub fn parse(source: &str) -> Result<Movie, String> {
+        let mut lines = source.lines().enumerate().filter(|(_, line)| {
+            let line = line.trim();
+            !line.is_empty() && !line.starts_with('#')
+        });
+        let mut next = |name: &str| {
+            lines.next()
+                .map(|(number, line)| (number + 1, line.trim()))
+                .ok_or(format!("The movie is missing its {}", name))
+        };
+
+        let (_, header) = next("header")?;
+        if header != HEADER {
+            return Err(format!("Expected the movie to start with {}", HEADER));
+        }
+
+        let (number, line) = next("seed")?;
+        let seed = line.strip_prefix("seed ")
+            .and_then(|seed| seed.trim().parse().ok())
+            .ok_or(format!("Invalid seed on line {}: {}", number, line))?;
+
+        let (number, line) = next("quirks")?;
+        let quirks = line.strip_prefix("quirks ")
+            .and_then(|flags| parse_quirks(flags.trim()))
+            .ok_or(format!("Invalid quirks on line {}: {}", number, line))?;
+
+        let (number, line) = next("instructions per frame")?;
+        let cycles_per_frame = line.strip_prefix("ipf ")
+            .and_then(|ipf| ipf.trim().parse().ok())
+            .filter(|ipf| *ipf > 0)
+            .ok_or(format!("Invalid instructions per frame on line {}: {}", number, line))?;
+
+        let mut movie = Movie::new(seed, quirks, cycles_per_frame);
+
+        for (number, line) in lines {
+            let line = line.trim();
+            let error = || format!("Invalid input on line {}: {}", number + 1, line);
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            if parts.len() != 3 {
+                return Err(error());
+            }
+
+            let cycle = parts[0].parse().map_err(|_| error())?;
+            let key = u8::from_str_radix(parts[1], 16).map_err(|_| error())?;
+            let state = match parts[2] {
+                "down" => KeyState::DOWN,
+                "up" => KeyState::UP,
+                _ => return Err(error())
+            };
+
+            if key > 0xF || movie.inputs.last().is_some_and(|last| last.cycle > cycle) {
+                return Err(error());
+            }
+
+            movie.record(cycle, key, state);
+        }
+
+        Ok(movie)
+    }
+}
+
+fn parse_quirks(flags: &str) -> Option<Quirks> {
+    let flags: Vec<bool> = flags.chars()
+        .map(|flag| match flag {
+            '0' => Some(false),
+            '1' => Some(true),
+            _ => None
+        })
+        .collect::<Option<_>>()?;
+
+    match flags.as_slice() {
+        [vf_reset, shift_uses_vy, load_store_increments_index, jump_uses_vx, wrap_sprites, load_store_increments_by_x] => {
+            Some(Quirks {
+                vf_reset: *vf_reset,
+                shift_uses_vy: *shift_uses_vy,
+                load_store_increments_index: *load_store_increments_index,
+                jump_uses_vx: *jump_uses_vx,
+                wrap_sprites: *wrap_sprites,
+                load_store_increments_by_x: *load_store_increments_by_x
+            })
+        },
+        _ => None
+    }
+}
+
+impl fmt::Display for Movie {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let quirks = self.quirks;
+        let flags: String = [
+            quirks.vf_reset,
+            quirks.shift_uses_vy,
+            quirks.load_store_increments_index,
+            quirks.jump_uses_vx,
+            quirks.wrap_sprites,
+            quirks.load_store_increments_by_x
+        ].iter().map(|flag| if *flag { '1' } else { '0' }).collect();
+
+        writeln!(f, "{}", HEADER)?;
+        writeln!(f, "seed {}", self.seed)?;
+        writeln!(f, "quirks {}", flags)?;
+        writeln!(f, "ipf {}", self.cycles_per_frame)?;
+
+        for input in self.inputs.iter() {
+            let state = match input.state {
+                KeyState::DOWN => "down",
+                KeyState::UP => "up"
+            };
+            writeln!(f, "{} {:X} {}", input.cycle, input.key, state)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Feeds the inputs of a movie to an emulator as the cycles go by
+pub struct Playback<'a> {
+    inputs: Peekable<Iter<'a, MovieInput>>
+}
+
+impl Playback<'_> {
+    /// Presses and releases the keys recorded before `cycle`
+    pub fn apply(&mut self, emu: &mut Emulator, cycle: u64) {
+        while let Some(input) = self.inputs.next_if(|input| input.cycle <= cycle) {
+            emu.set_key(input.key, input.state);
+        }
+    }
+
+    /// Whether every input has been played
+    pub fn is_finished(&mut self) -> bool {
+        self.inputs.peek().is_none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+
+    #[test]
+    fn round_trip() {
+        let mut movie = Movie::new(1234, Quirks::COSMAC_VIP, 10);
+        movie.record(120, 0x5, KeyState::DOWN);
+        movie.record(180, 0xA, KeyState::UP);
+
+        let text = movie.to_string();
+        assert_eq!("chip8-movie 1\nseed 1234\nquirks 111000\nipf 10\n120 5 down\n180 A up\n", text);
+        assert_eq!(Ok(movie), Movie::parse(&text));
+    }
+
+    #[test]
+    fn invalid_movies() {
+        assert!(Movie::parse("seed 1").is_err());
+        assert!(Movie::parse("chip8-movie 1\nseed 1\nquirks 101\nipf 10").is_err());
+        assert!(Movie::parse("chip8-movie 1\nseed 1\nquirks 00000\nipf 0").is_err());
+        assert!(Movie::parse("chip8-movie 1\nseed 1\nquirks 00000\nipf 10\n10 5 down\n5 5 up").is_err());
+    }
+
+    #[test]
+    fn replays_identically() {
+        // Draw random numbers until key 7 is pressed, then until it is released
+        let rom = assemble("
+            LD V0, 7
+            press: RND V1, 0xFF
+            SKP V0
+            JP press
+            release: RND V2, 0xFF
+            SKNP V0
+            JP release
+            done: JP done
+        ").unwrap();
+        let mut movie = Movie::new(99, Quirks::default(), 1);
+        movie.record(3, 0x7, KeyState::DOWN);
+        movie.record(6, 0x7, KeyState::UP);
+
+        let run = || {
+            let mut emu = movie.emulator(&rom).unwrap();
+            let mut playback = movie.play();
+            for cycle in 0..20 {
+                playback.apply(&mut emu, cycle);
+                emu.run_frame(movie.cycles_per_frame).unwrap();
+            }
+            assert!(playback.is_finished());
+            emu
+        };
+
+        let (first, second) = (run(), run());
+        assert_eq!(first.save_state(), second.save_state());
+        assert_eq!(0x20E, first.program_counter);
+    }
+}
@@ -0,0 +1,170 @@
+//! The colours the display is drawn in, one for each combination of the two
+//! XO-CHIP bitplanes. Classic programs only draw on the first plane so only
+//! use the first two colours.
+
+use std::fmt;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Colour {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8
+}
+
+impl Colour {
+    pub const fn new(r: u8, g: u8, b: u8) -> Colour {
+        Colour { r, g, b }
+    }
+
+    /// Reads a colour written as `#RRGGBB`, the `#` being optional
+    pub fn from_hex(hex: &str) -> Option<Colour> {
+        let hex = hex.strip_prefix('#').unwrap_or(hex);
+        if hex.len() != 6 || !hex.is_ascii() {
+            return None;
+        }
+
+        let channel = |at: usize| u8::from_str_radix(&hex[at..at + 2], 16).ok();
+        Some(Colour::new(channel(0)?, channel(2)?, channel(4)?))
+    }
+
+    /// The colour `amount` of the way from this one to `other`
+    pub fn mix(self, other: Colour, amount: f32) -> Colour {
+        let channel = |from: u8, to: u8| (from as f32 + (to as f32 - from as f32) * amount).round() as u8;
+        Colour::new(channel(self.r, other.r), channel(self.g, other.g), channel(self.b, other.b))
+    }
+}
+
+impl fmt::Display for Colour {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{:02X}{:02X}{:02X}", self.r, self.g, self.b)
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Palette {
+    pub name: String,
+    /// Indexed by `Emulator::get_colour`
+    pub colours: [Colour; 4]
+}
+
+const PRESETS: [(&str, [Colour; 4]); 4] = [
+    ("classic", [
+        Colour::new(0x00, 0x00, 0x00),
+        Colour::new(0xFF, 0xFF, 0xFF),
+        Colour::new(0xAA, 0xAA, 0xAA),
+        Colour::new(0x55, 0x55, 0x55)
+    ]),
+    // Green and amber phosphor monitors
+    ("green", [
+        Colour::new(0x0A, 0x1A, 0x0A),
+        Colour::new(0x33, 0xFF, 0x66),
+        Colour::new(0x22, 0xAA, 0x44),
+        Colour::new(0x11, 0x55, 0x22)
+    ]),
+    ("amber", [
+        Colour::new(0x1A, 0x0F, 0x00),
+        Colour::new(0xFF, 0xB0, 0x00),
+        Colour::new(0xAA, 0x75, 0x00),
+        Colour::new(0x55, 0x3A, 0x00)
+    ]),
+    // The defaults of the Octo IDE
+    ("octo", [
+        Colour::new(0x99, 0x66, 0x00),
+        Colour::new(0xFF, 0xCC, 0x00),
+        Colour::new(0xFF, 0x66, 0x00),
+        Colour::new(0x66, 0x22, 0x00)
+    ])
+];
+
+impl Palette {
+    pub const PRESET_NAMES: [&'static str; 4] = ["classic", "green", "amber", "octo"];
+
+    pub fn preset(name: &str) -> Option<Palette> {
+        PRESETS.iter()
+            .find(|(preset, _)| *preset == name)
+            .map(|(name, colours)| Palette { name: name.to_string(), colours: *colours })
+    }
+
+    /// Every preset, in the order of `PRESET_NAMES`
+    pub fn presets() -> Vec<Palette> {
+        Palette::PRESET_NAMES.iter().filter_map(|name| Palette::preset(name)).collect()
+    }
+
+    /// A palette from two or four hex colours. With two, the colours for the
+    /// second plane are shades between them.
+    pub fn from_hex(colours: &[&str]) -> Result<Palette, String> {
+        let colours = colours.iter()
+            .map(|hex| Colour::from_hex(hex).ok_or(format!("Expected a colour like #FF8800, got {}", hex)))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let colours = match colours[..] {
+            [off, on] => [off, on, off.mix(on, 2.0 / 3.0), off.mix(on, 1.0 / 3.0)],
+            [off, on, second, both] => [off, on, second, both],
+            _ => return Err(format!("Expected 2 or 4 colours, got {}", colours.len()))
+        };
+
+        Ok(Palette { name: "custom".to_string(), colours })
+    }
+
+    /// Reads the name of a preset, or hex colours separated by commas
+    pub fn parse(source: &str) -> Result<Palette, String> {
+        match Palette::preset(source) {
+            Some(palette) => Ok(palette),
+            None if source.contains(',') => {
+                Palette::from_hex(&source.split(',').map(str::trim).collect::<Vec<_>>())
+            },
+            None => Err(format!(
+                "Unknown palette {}, expected one of {} or a list of colours",
+                source,
+                Palette::PRESET_NAMES.join(", ")
+            ))
+        }
+    }
+
+    /// The colour of a pixel from `Emulator::get_colour`
+    pub fn colour(&self, index: u8) -> Colour {
+        self.colours[index as usize & 3]
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Palette {
+        Palette::preset("classic").unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_preset_name_resolves() {
+        for name in Palette::PRESET_NAMES.iter() {
+            assert_eq!(Ok(*name), Palette::parse(name).map(|palette| palette.name).as_deref());
+        }
+        assert_eq!(Palette::PRESET_NAMES.len(), PRESETS.len());
+    }
+
+    #[test]
+    fn hex_colours() {
+        assert_eq!(Some(Colour::new(0xFF, 0x88, 0x00)), Colour::from_hex("#FF8800"));
+        assert_eq!(Some(Colour::new(0x12, 0x34, 0xab)), Colour::from_hex("1234ab"));
+        assert_eq!(None, Colour::from_hex("#FF88"));
+        assert_eq!(None, Colour::from_hex("#GG8800"));
+        assert_eq!("#0A0B0C", Colour::new(10, 11, 12).to_string());
+    }
+
+    #[test]
+    fn custom_palettes() {
+        let palette = Palette::parse("#000000, #FFFFFF").unwrap();
+        assert_eq!(Colour::new(0xAA, 0xAA, 0xAA), palette.colour(2));
+        assert_eq!(Colour::new(0x55, 0x55, 0x55), palette.colour(3));
+
+        let palette = Palette::parse("#000000,#FFFFFF,#FF0000,#00FF00").unwrap();
+        assert_eq!(Colour::new(0x00, 0xFF, 0x00), palette.colour(3));
+
+        assert!(Palette::parse("#000000").is_err());
+        assert!(Palette::parse("#000000,#FFFFFF,#FF0000").is_err());
+        assert!(Palette::parse("purple").is_err());
+    }
+}
@@ -0,0 +1,297 @@
+//! Records the frames shown at 60 fps, either to an animated GIF or to a
+//! directory of numbered PNGs for other tools to put together.
+
+use crate::image::Image;
+use crate::palette::Colour;
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+
+const FRAMES_PER_SECOND: u64 = 60;
+
+/// Writes an animated GIF that loops forever, given a frame every 1/60th of a
+/// second. Frames that are the same as the one before only lengthen how long
+/// it's shown, as GIF delays are in hundredths of a second anyway.
+pub struct GifWriter<W: Write> {
+    encoder: gif::Encoder<W>,
+    width: u32,
+    height: u32,
+    /// The frame waiting for one that's different, and the frame number it
+    /// was first shown on
+    pending: Option<(Image, u64)>,
+    frames: u64
+}
+
+impl<W: Write> GifWriter<W> {
+    /// Every frame is scaled to `width` by `height`
+    pub fn new(writer: W, width: u32, height: u32) -> io::Result<GifWriter<W>> {
+        // Each frame has its own colours rather than a global table
+        let mut encoder = gif::Encoder::new(writer, width as u16, height as u16, &[]).map_err(io_error)?;
+        encoder.set_repeat(gif::Repeat::Infinite).map_err(io_error)?;
+
+        Ok(GifWriter { encoder, width, height, pending: None, frames: 0 })
+    }
+
+    /// Adds the next frame
+    pub fn write(&mut self, image: &Image) -> io::Result<()> {
+        let image = image.resized(self.width, self.height);
+
+        match &self.pending {
+            Some((pending, _)) if *pending == image => (),
+            _ => {
+                self.flush_pending()?;
+                self.pending = Some((image, self.frames));
+            }
+        }
+
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Writes the last frame and the trailer, returning the writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.flush_pending()?;
+        let mut writer = self.encoder.into_inner()?;
+        writer.flush()?;
+
+        Ok(writer)
+    }
+
+    /// Writes the pending frame, shown until the current frame
+    fn flush_pending(&mut self) -> io::Result<()> {
+        let (image, start) = match self.pending.take() {
+            Some(pending) => pending,
+            None => return Ok(())
+        };
+
+        // Round the start and end separately so the delays add up to the
+        // right length, eg 2, 2, 1 for three frames in a row
+        let centiseconds = |frame: u64| (frame * 100 + FRAMES_PER_SECOND / 2) / FRAMES_PER_SECOND;
+        let delay = centiseconds(self.frames) - centiseconds(start);
+
+        let (table, indices) = index_colours(&image.pixels);
+        let palette: Vec<u8> = table.iter().flat_map(|colour| vec![colour.r, colour.g, colour.b]).collect();
+
+        let mut frame = gif::Frame::from_palette_pixels(self.width as u16, self.height as u16, indices, palette, None);
+        frame.delay = delay as u16;
+        self.encoder.write_frame(&frame).map_err(io_error)
+    }
+}
+
+fn io_error(error: gif::EncodingError) -> io::Error {
+    match error {
+        gif::EncodingError::Io(error) => error,
+        error => io::Error::new(io::ErrorKind::InvalidInput, error.to_string())
+    }
+}
+
+/// Picks up to 256 colours for a frame, returning them along with the index
+/// of each pixel. Any more colours, from a fading filter, become the closest
+/// of the first 256.
+fn index_colours(pixels: &[Colour]) -> (Vec<Colour>, Vec<u8>) {
+    let mut table: Vec<Colour> = Vec::new();
+    let mut indices = Vec::with_capacity(pixels.len());
+    let mut known: HashMap<(u8, u8, u8), u8> = HashMap::new();
+
+    for colour in pixels {
+        let key = (colour.r, colour.g, colour.b);
+        let index = match known.get(&key) {
+            Some(index) => *index,
+            None => {
+                let index = if table.len() < 256 {
+                    table.push(*colour);
+                    table.len() - 1
+                } else {
+                    let distance = |other: &Colour| {
+                        let channel = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+                        channel(colour.r, other.r) + channel(colour.g, other.g) + channel(colour.b, other.b)
+                    };
+                    (0..table.len()).min_by_key(|i| distance(&table[*i])).unwrap_or(0)
+                };
+
+                known.insert(key, index as u8);
+                index as u8
+            }
+        };
+
+        indices.push(index);
+    }
+
+    (table, indices)
+}
+
+/// Where recorded frames go
+enum Output {
+    Gif(GifWriter<BufWriter<File>>),
+    /// A directory of PNGs, each scaled to the size given
+    Frames { dir: PathBuf, width: u32, height: u32 }
+}
+
+/// Records frames to a GIF when the path ends in `.gif`, otherwise to
+/// `frame-00000.png`, `frame-00001.png`... in a directory
+pub struct Recorder {
+    output: Output,
+    frames: u64
+}
+
+impl Recorder {
+    /// Starts a recording with frames of `width` by `height`, creating the
+    /// directory for PNG frames if need be
+    pub fn create(path: &str, width: u32, height: u32) -> io::Result<Recorder> {
+        let output = if path.to_lowercase().ends_with(".gif") {
+            let file = File::create(path)?;
+            Output::Gif(GifWriter::new(BufWriter::new(file), width, height)?)
+        } else {
+            fs::create_dir_all(path)?;
+            Output::Frames { dir: PathBuf::from(path), width, height }
+        };
+
+        Ok(Recorder { output, frames: 0 })
+    }
+
+    /// The number of frames recorded so far
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Records the next frame, a 60th of a second after the last
+    pub fn write(&mut self, image: &Image) -> io::Result<()> {
+        match &mut self.output {
+            Output::Gif(gif) => gif.write(image)?,
+            Output::Frames { dir, width, height } => {
+                let file = File::create(dir.join(format!("frame-{:05}.png", self.frames)))?;
+                image.resized(*width, *height).write_png(BufWriter::new(file))?;
+            }
+        }
+
+        self.frames += 1;
+        Ok(())
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        match self.output {
+            Output::Gif(gif) => gif.finish().map(|_| ()),
+            Output::Frames { .. } => Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The delay and pixels of each frame
+    type Frames = Vec<(u16, Vec<Colour>)>;
+
+    /// Decodes a GIF with the `gif` crate, returning its size and frames
+    fn decode_gif(bytes: &[u8]) -> ((u16, u16), Frames) {
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::RGBA);
+        let mut decoder = options.read_info(bytes).unwrap();
+        let size = (decoder.width(), decoder.height());
+
+        let mut frames = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            let pixels: Vec<Colour> = frame.buffer
+                .chunks(4)
+                .map(|rgba| Colour::new(rgba[0], rgba[1], rgba[2]))
+                .collect();
+            frames.push((frame.delay, pixels));
+        }
+
+        (size, frames)
+    }
+
+    #[test]
+    fn colours_are_indexed() {
+        let (red, blue) = (Colour::new(255, 0, 0), Colour::new(0, 0, 255));
+        let (table, indices) = index_colours(&[red, blue, red]);
+
+        assert_eq!(vec![red, blue], table);
+        assert_eq!(vec![0, 1, 0], indices);
+
+        // Past 256 colours the closest is used
+        let many: Vec<Colour> = (0..=256).map(|i| Colour::new((i / 2) as u8, (i % 2) as u8 * 100, 0)).collect();
+        let (table, indices) = index_colours(&many);
+        assert_eq!(256, table.len());
+        assert_eq!(many[254], table[indices[256] as usize]);
+    }
+
+    #[test]
+    fn repeated_frames_lengthen_the_delay() {
+        let (off, on) = (Colour::new(0, 0, 0), Colour::new(255, 255, 255));
+        let mut gif = GifWriter::new(Vec::new(), 2, 1).unwrap();
+
+        for _ in 0..3 {
+            gif.write(&Image::from_colours(&[on, off], 2, 1, 1)).unwrap();
+        }
+        gif.write(&Image::from_colours(&[off, on], 2, 1, 1)).unwrap();
+        let bytes = gif.finish().unwrap();
+
+        // Three frames are 5 hundredths of a second, one is 2
+        let (_, frames) = decode_gif(&bytes);
+        let delays: Vec<u16> = frames.iter().map(|(delay, _)| *delay).collect();
+        assert_eq!(vec![5, 2], delays);
+    }
+
+    #[test]
+    fn gif_decodes() {
+        // Frames of a couple of hundred colours in noisy patterns
+        let mut state = 7u32;
+        let frames: Vec<Image> = (0..3)
+            .map(|frame| {
+                let colours: Vec<Colour> = (0..64 * 32)
+                    .map(|_| {
+                        state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                        let index = (state >> 16) % 200;
+                        Colour::new(index as u8, (index * frame) as u8, 255 - index as u8)
+                    })
+                    .collect();
+                Image::from_colours(&colours, 64, 32, 2)
+            })
+            .collect();
+
+        let mut gif = GifWriter::new(Vec::new(), 128, 64).unwrap();
+        for image in &frames {
+            gif.write(image).unwrap();
+            gif.write(image).unwrap();
+        }
+        let bytes = gif.finish().unwrap();
+
+        let (size, decoded) = decode_gif(&bytes);
+        assert_eq!((128, 64), size);
+
+        let expected: Frames = frames.into_iter()
+            .zip(&[3, 4, 3])
+            .map(|(image, delay)| (*delay, image.pixels))
+            .collect();
+        assert_eq!(expected, decoded);
+    }
+
+    #[test]
+    fn frames_are_scaled_to_the_recording() {
+        let (off, on) = (Colour::new(0, 0, 0), Colour::new(255, 255, 255));
+        let dir = std::env::temp_dir().join(format!("chip8-frames-{}", std::process::id()));
+
+        // A lores frame, then a hires one twice the size, as when a program
+        // switches resolution part way through
+        let mut recorder = Recorder::create(dir.to_str().unwrap(), 256, 128).unwrap();
+        recorder.write(&Image::from_colours(&[on; 64 * 32], 64, 32, 2)).unwrap();
+        recorder.write(&Image::from_colours(&[off; 128 * 64], 128, 64, 2)).unwrap();
+        recorder.finish().unwrap();
+
+        for (frame, colour) in [on, off].iter().enumerate() {
+            let file = File::open(dir.join(format!("frame-{:05}.png", frame))).unwrap();
+            let mut reader = png::Decoder::new(file).read_info().unwrap();
+            let mut pixels = vec![0; reader.output_buffer_size()];
+            let info = reader.next_frame(&mut pixels).unwrap();
+
+            assert_eq!((256, 128), (info.width, info.height));
+            assert!(pixels[..info.buffer_size()].iter().all(|channel| *channel == colour.r));
+        }
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
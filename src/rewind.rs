@@ -0,0 +1,197 @@
+use crate::emulator::{Chip8Error, Emulator};
+
+use std::collections::VecDeque;
+
+/// A ring buffer of recent save states for running a program backwards.
+///
+/// Only the newest state is kept whole, every older one is stored as the
+/// difference to the state after it, which is small as most of memory is
+/// untouched from one frame to the next.
+pub struct Rewind {
+    capacity: usize,
+    latest: Option<Vec<u8>>,
+    /// `deltas[i]` turns the `i`th oldest state into the one after it and back
+    deltas: VecDeque<Vec<u8>>
+}
+
+impl Rewind {
+    /// Keeps up to `capacity` states, eg 30 seconds of frames at 60 fps is 1800
+    pub fn new(capacity: usize) -> Rewind {
+        Rewind {
+            capacity: capacity.max(1),
+            latest: None,
+            deltas: VecDeque::new()
+        }
+    }
+
+    /// The number of states that can be rewound through
+    pub fn len(&self) -> usize {
+        match self.latest {
+            Some(_) => self.deltas.len() + 1,
+            None => 0
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.latest.is_none()
+    }
+
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.deltas.clear();
+    }
+
+    /// Records the current state of the emulator, forgetting the oldest state
+    /// when the buffer is full
+    pub fn push(&mut self, emu: &Emulator) {
+        let state = emu.save_state();
+
+        if let Some(latest) = &self.latest {
+            self.deltas.push_back(encode(latest, &state));
+
+            if self.deltas.len() >= self.capacity {
+                self.deltas.pop_front();
+            }
+        }
+
+        self.latest = Some(state);
+    }
+
+    /// Restores the state recorded before the newest one, which is dropped.
+    /// Returns false once there is nothing older to go back to, leaving the
+    /// emulator at the oldest state.
+    pub fn step_back(&mut self, emu: &mut Emulator) -> Result<bool, Chip8Error> {
+        let latest = match &mut self.latest {
+            Some(latest) => latest,
+            None => return Ok(false)
+        };
+
+        let stepped = match self.deltas.pop_back() {
+            Some(delta) => {
+                apply(latest, &delta);
+                true
+            },
+            None => false
+        };
+
+        emu.load_state(latest)?;
+        Ok(stepped)
+    }
+
+    /// The bytes used to hold every state, to see how well they compress
+    pub fn size(&self) -> usize {
+        let latest = self.latest.as_ref().map_or(0, Vec::len);
+        latest + self.deltas.iter().map(Vec::len).sum::<usize>()
+    }
+}
+
+/// Encodes the XOR of two states of the same length as runs of
+/// `<unchanged u32> <changed u32> <changed bytes>`
+fn encode(old: &[u8], new: &[u8]) -> Vec<u8> {
+    let mut delta = Vec::new();
+    let mut i = 0;
+
+    while i < new.len() {
+        let start = i;
+        while i < new.len() && old[i] == new[i] {
+            i += 1;
+        }
+        let unchanged = i - start;
+
+        let start = i;
+        while i < new.len() && old[i] != new[i] {
+            i += 1;
+        }
+
+        if start == i {
+            // The states end the same, so there is nothing left to record
+            break;
+        }
+
+        delta.extend(&(unchanged as u32).to_be_bytes());
+        delta.extend(&((i - start) as u32).to_be_bytes());
+        delta.extend(old[start..i].iter().zip(&new[start..i]).map(|(old, new)| old ^ new));
+    }
+
+    delta
+}
+
+/// Applies a delta from `encode` to either of the states it was made from,
+/// turning it into the other
+fn apply(state: &mut [u8], delta: &[u8]) {
+    let read = |at: usize| {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(&delta[at..at + 4]);
+        u32::from_be_bytes(bytes) as usize
+    };
+
+    let mut i = 0;
+    let mut at = 0;
+
+    while at < delta.len() {
+        i += read(at);
+        let changed = read(at + 4);
+        at += 8;
+
+        for (byte, xor) in state[i..i + changed].iter_mut().zip(&delta[at..at + changed]) {
+            *byte ^= xor;
+        }
+
+        i += changed;
+        at += changed;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deltas_round_trip() {
+        let old = [1, 2, 3, 4, 5, 6, 7, 8];
+        let new = [1, 9, 9, 4, 5, 6, 7, 0];
+        let delta = encode(&old, &new);
+
+        let mut state = old;
+        apply(&mut state, &delta);
+        assert_eq!(new, state);
+
+        apply(&mut state, &delta);
+        assert_eq!(old, state);
+        assert!(encode(&old, &old).is_empty());
+    }
+
+    #[test]
+    fn steps_back_through_frames() {
+        let mut emu = Emulator::default();
+        let mut rewind = Rewind::new(3);
+
+        for value in 1..=5 {
+            emu.memory[0x300] = value;
+            rewind.push(&emu);
+        }
+        assert_eq!(3, rewind.len());
+
+        assert_eq!(Ok(true), rewind.step_back(&mut emu));
+        assert_eq!(4, emu.memory[0x300]);
+        assert_eq!(Ok(true), rewind.step_back(&mut emu));
+        assert_eq!(3, emu.memory[0x300]);
+
+        // The oldest state stays put once everything newer is gone
+        assert_eq!(Ok(false), rewind.step_back(&mut emu));
+        assert_eq!(3, emu.memory[0x300]);
+        assert_eq!(1, rewind.len());
+    }
+
+    #[test]
+    fn unchanged_frames_are_small() {
+        let emu = Emulator::default();
+        let mut rewind = Rewind::new(10);
+
+        rewind.push(&emu);
+        let full = rewind.size();
+        rewind.push(&emu);
+
+        assert_eq!(full, rewind.size());
+    }
+}
@@ -0,0 +1,180 @@
+//! The pieces of a frontend that plays in a terminal: drawing the display
+//! with half block characters, two rows of pixels to a line, and reading
+//! keys from the bytes typed.
+
+use crate::palette::Colour;
+
+use std::fmt::Write;
+
+/// How long keys are held after being typed, in frames. Terminals only say
+/// when a key is typed, not when it's let go, so a key is held long enough
+/// for the keyboard to start repeating it and then for each repeat.
+const FIRST_HOLD: u64 = 30;
+const REPEAT_HOLD: u64 = 6;
+
+/// Draws a frame of colours `width` pixels wide from the top left of the
+/// terminal. Each character is the upper half block in the colour of the
+/// top pixel over a background of the bottom one.
+pub fn render(colours: &[Colour], width: usize) -> String {
+    let mut out = String::from("\x1b[H");
+    let rows: Vec<&[Colour]> = colours.chunks(width.max(1)).collect();
+
+    for pair in rows.chunks(2) {
+        let mut last = None;
+
+        for (x, top) in pair[0].iter().enumerate() {
+            let bottom = pair.get(1).map_or(*top, |row| row[x]);
+
+            // Only change colour when it differs from the character before
+            if last != Some((*top, bottom)) {
+                let _ = write!(
+                    out,
+                    "\x1b[38;2;{};{};{};48;2;{};{};{}m",
+                    top.r, top.g, top.b, bottom.r, bottom.g, bottom.b
+                );
+                last = Some((*top, bottom));
+            }
+            out.push('▀');
+        }
+
+        out.push_str("\x1b[0m\n");
+    }
+
+    out
+}
+
+/// Names the keys in bytes read from a terminal the way SDL names them, so
+/// they can be looked up in a `KeyMap`. Escape and Ctrl-C are named too, to
+/// quit with.
+pub fn key_names(bytes: &[u8]) -> Vec<String> {
+    let mut names = Vec::new();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let name = match bytes[i] {
+            // Escape sequences for the arrow keys, either `ESC [ A` or
+            // `ESC O A`, skipping any others
+            0x1B if i + 1 < bytes.len() && (bytes[i + 1] == b'[' || bytes[i + 1] == b'O') => {
+                let end = (i + 2..bytes.len())
+                    .find(|at| (0x40..=0x7E).contains(&bytes[*at]))
+                    .unwrap_or(bytes.len() - 1);
+                let name = match bytes[end] {
+                    b'A' => Some("Up"),
+                    b'B' => Some("Down"),
+                    b'C' => Some("Right"),
+                    b'D' => Some("Left"),
+                    _ => None
+                };
+
+                i = end + 1;
+                match name {
+                    Some(name) => name.to_string(),
+                    None => continue
+                }
+            },
+            0x1B => {
+                i += 1;
+                "Escape".to_string()
+            },
+            byte => {
+                i += 1;
+                match byte {
+                    0x03 => "Ctrl-C".to_string(),
+                    b'\r' | b'\n' => "Return".to_string(),
+                    b' ' => "Space".to_string(),
+                    0x7F | 0x08 => "Backspace".to_string(),
+                    b'\t' => "Tab".to_string(),
+                    0x21..=0x7E => (byte as char).to_ascii_uppercase().to_string(),
+                    _ => continue
+                }
+            }
+        };
+
+        names.push(name);
+    }
+
+    names
+}
+
+/// The Chip8 keys held down since they were typed
+#[derive(Default)]
+pub struct HeldKeys {
+    /// Each key with the frame it's let go on
+    held: Vec<(u8, u64)>
+}
+
+impl HeldKeys {
+    pub fn new() -> HeldKeys {
+        HeldKeys::default()
+    }
+
+    /// Holds a key typed on `frame`, returning true if it wasn't already held
+    pub fn press(&mut self, key: u8, frame: u64) -> bool {
+        match self.held.iter_mut().find(|(held, _)| *held == key) {
+            Some((_, until)) => {
+                *until = (*until).max(frame + REPEAT_HOLD);
+                false
+            },
+            None => {
+                self.held.push((key, frame + FIRST_HOLD));
+                true
+            }
+        }
+    }
+
+    /// Lets go of the keys that haven't been typed for long enough by
+    /// `frame`, returning them
+    pub fn release(&mut self, frame: u64) -> Vec<u8> {
+        let released = self.held.iter()
+            .filter(|(_, until)| *until <= frame)
+            .map(|(key, _)| *key)
+            .collect();
+
+        self.held.retain(|(_, until)| *until > frame);
+        released
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn half_blocks() {
+        let (off, on) = (Colour::new(0, 0, 0), Colour::new(255, 255, 255));
+        let frame = render(&[on, on, off, on], 2);
+
+        assert_eq!(
+            "\x1b[H\x1b[38;2;255;255;255;48;2;0;0;0m▀\x1b[38;2;255;255;255;48;2;255;255;255m▀\x1b[0m\n",
+            frame
+        );
+
+        // Runs of the same colours share an escape code, and an odd last row
+        // is drawn over itself
+        let frame = render(&[on, on, on], 3);
+        assert_eq!(1, frame.matches("38;2").count());
+        assert_eq!(3, frame.matches('▀').count());
+    }
+
+    #[test]
+    fn naming_keys() {
+        assert_eq!(vec!["X", "1", "Up", "Left", "Space", "Escape"], key_names(b"x1\x1b[A\x1bOD \x1b"));
+        assert_eq!(vec!["Ctrl-C", "Return"], key_names(b"\x03\r"));
+        // Other escape sequences, like function keys, are skipped
+        assert_eq!(vec!["Q"], key_names(b"\x1b[15~q"));
+    }
+
+    #[test]
+    fn keys_are_held_until_the_repeats_stop() {
+        let mut keys = HeldKeys::new();
+
+        assert!(keys.press(0x5, 0));
+        assert!(keys.release(FIRST_HOLD - 1).is_empty());
+
+        // Repeating keeps it held
+        assert!(!keys.press(0x5, FIRST_HOLD - 1));
+        assert!(keys.release(FIRST_HOLD).is_empty());
+        assert_eq!(vec![0x5], keys.release(FIRST_HOLD - 1 + REPEAT_HOLD));
+        assert!(keys.press(0x5, 100));
+    }
+}